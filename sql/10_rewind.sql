@@ -0,0 +1,29 @@
+-- drops everything after after_block, rollups from the hour of the first dropped block, and
+-- records the reorg; shared by the indexer and the api's linkage checker
+CREATE OR REPLACE FUNCTION rewind_blocks(after_block INTEGER) RETURNS VOID AS $$
+DECLARE
+    fork_hour INTEGER;
+BEGIN
+    SELECT timestamp / 3600 * 3600 INTO fork_hour FROM blocks WHERE number > after_block ORDER BY number LIMIT 1;
+    IF fork_hour IS NULL THEN
+        RETURN;
+    END IF;
+    -- waits out a rollup in progress, which reads the progress under the same row lock
+    PERFORM 1 FROM rollup_progress FOR UPDATE;
+    DELETE FROM swap_rollups_hourly WHERE hour >= fork_hour AND EXISTS (SELECT 1 FROM rollup_progress WHERE block_number > after_block);
+    UPDATE rollup_progress SET block_number = (SELECT coalesce(max(number), 0) FROM blocks WHERE timestamp < fork_hour) WHERE block_number > after_block;
+    INSERT INTO reorgs (detected_at, fork_block, old_tip, orphaned_swaps, orphaned_reserves)
+        SELECT extract(epoch FROM now())::bigint, after_block, (SELECT max(number) FROM blocks), (SELECT count(*) FROM swaps WHERE block_number > after_block), (SELECT count(*) FROM reserves WHERE block_number > after_block);
+    DELETE FROM swaps WHERE block_number > after_block;
+    DELETE FROM reserves WHERE block_number > after_block;
+    DELETE FROM blocks WHERE number > after_block;
+END;
+$$ LANGUAGE plpgsql;
+-- the first of the newest depth blocks whose parent hash is not the hash of the stored block
+-- before it, null while they all link up
+CREATE OR REPLACE FUNCTION first_unlinked_block(depth INTEGER) RETURNS INTEGER AS $$
+    SELECT child.number FROM blocks child JOIN blocks parent ON parent.number = child.number - 1
+    WHERE child.number > (SELECT max(number) FROM blocks) - depth
+    AND child.parent_hash IS NOT NULL AND child.parent_hash <> parent.hash
+    ORDER BY child.number LIMIT 1;
+$$ LANGUAGE sql STABLE;
@@ -0,0 +1,12 @@
+CREATE TABLE IF NOT EXISTS alerts (
+             id VARCHAR(36) PRIMARY KEY,
+             account_id VARCHAR(36) NOT NULL,
+             pool_contract_address VARCHAR(40) NOT NULL,
+             side INTEGER NOT NULL,
+             above BOOLEAN NOT NULL,
+             price NUMERIC NOT NULL,
+             recurring BOOLEAN NOT NULL DEFAULT false,
+             active BOOLEAN NOT NULL DEFAULT true,
+             checked_block INTEGER,
+             notified_at BIGINT);
+CREATE INDEX IF NOT EXISTS alerts_account_id ON alerts (account_id);
@@ -0,0 +1,5 @@
+CREATE TABLE IF NOT EXISTS watchlist (
+             account_id VARCHAR(36) NOT NULL,
+             pool_contract_address VARCHAR(40) NOT NULL,
+             created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+             PRIMARY KEY (account_id, pool_contract_address));
@@ -0,0 +1,3 @@
+CREATE TABLE IF NOT EXISTS digest_subscriptions (
+             account_id VARCHAR(36) PRIMARY KEY,
+             created_at TIMESTAMPTZ NOT NULL DEFAULT now());
@@ -0,0 +1,17 @@
+CREATE TABLE IF NOT EXISTS swap_rollups_hourly (
+             pool_contract_address VARCHAR(40) NOT NULL,
+             hour INTEGER NOT NULL,
+             sum_in0 NUMERIC,
+             sum_in0_eth NUMERIC,
+             sum_in1 NUMERIC,
+             sum_in1_eth NUMERIC,
+             sum_eth NUMERIC,
+             count0 BIGINT NOT NULL,
+             count1 BIGINT NOT NULL,
+             min_price NUMERIC,
+             max_price NUMERIC,
+             PRIMARY KEY (pool_contract_address, hour));
+CREATE INDEX IF NOT EXISTS swap_rollups_hourly_hour ON swap_rollups_hourly (hour);
+CREATE TABLE IF NOT EXISTS rollup_progress (
+             id BOOLEAN PRIMARY KEY DEFAULT true,
+             block_number INTEGER NOT NULL);
@@ -0,0 +1,35 @@
+CREATE TABLE IF NOT EXISTS blocks (
+             number INTEGER PRIMARY KEY,
+             hash VARCHAR(64) NOT NULL,
+             timestamp INTEGER NOT NULL);
+CREATE INDEX IF NOT EXISTS blocks_timestamp ON blocks (timestamp);
+CREATE TABLE IF NOT EXISTS coins (
+             contract_address VARCHAR(40) PRIMARY KEY,
+             name TEXT NOT NULL,
+             symbol TEXT NOT NULL,
+             decimals INTEGER NOT NULL);
+CREATE TABLE IF NOT EXISTS pools (
+             contract_address VARCHAR(40) PRIMARY KEY,
+             token0 VARCHAR(40) NOT NULL,
+             token1 VARCHAR(40) NOT NULL);
+CREATE INDEX IF NOT EXISTS pools_token0 ON pools (token0);
+CREATE INDEX IF NOT EXISTS pools_token1 ON pools (token1);
+CREATE TABLE IF NOT EXISTS reserves (
+             contract_address VARCHAR(40) NOT NULL,
+             block_number INTEGER NOT NULL,
+             x TEXT NOT NULL,
+             y TEXT NOT NULL,
+             PRIMARY KEY (contract_address, block_number));
+CREATE TABLE IF NOT EXISTS swaps (
+             pool_contract_address VARCHAR(40) NOT NULL,
+             block_number INTEGER NOT NULL,
+             transaction_index INTEGER NOT NULL,
+             in0_eth NUMERIC,
+             in1_eth NUMERIC,
+             in0 NUMERIC,
+             in1 NUMERIC,
+             out0 NUMERIC,
+             out1 NUMERIC);
+ALTER TABLE swaps ADD COLUMN IF NOT EXISTS log_index INTEGER NOT NULL DEFAULT 0;
+CREATE INDEX IF NOT EXISTS swaps_pool_block ON swaps (pool_contract_address, block_number, transaction_index);
+CREATE INDEX IF NOT EXISTS swaps_block ON swaps (block_number);
@@ -0,0 +1,8 @@
+ALTER TABLE blocks ADD COLUMN IF NOT EXISTS parent_hash VARCHAR(64);
+CREATE TABLE IF NOT EXISTS reorgs (
+             id SERIAL PRIMARY KEY,
+             detected_at BIGINT NOT NULL,
+             fork_block INTEGER NOT NULL,
+             old_tip INTEGER NOT NULL,
+             orphaned_swaps BIGINT NOT NULL,
+             orphaned_reserves BIGINT NOT NULL);
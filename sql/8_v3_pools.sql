@@ -0,0 +1,7 @@
+ALTER TABLE pools ADD COLUMN IF NOT EXISTS protocol VARCHAR(8) NOT NULL DEFAULT 'v2';
+ALTER TABLE pools ADD COLUMN IF NOT EXISTS fee INTEGER;
+ALTER TABLE reserves ADD COLUMN IF NOT EXISTS sqrt_price_x96 TEXT;
+ALTER TABLE reserves ADD COLUMN IF NOT EXISTS tick INTEGER;
+ALTER TABLE reserves ADD COLUMN IF NOT EXISTS liquidity TEXT;
+ALTER TABLE swaps ADD COLUMN IF NOT EXISTS amount0 NUMERIC;
+ALTER TABLE swaps ADD COLUMN IF NOT EXISTS amount1 NUMERIC;
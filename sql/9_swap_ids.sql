@@ -0,0 +1,2 @@
+ALTER TABLE swaps ADD COLUMN IF NOT EXISTS id BIGSERIAL;
+CREATE INDEX IF NOT EXISTS swaps_pool_id ON swaps (pool_contract_address, id);
@@ -0,0 +1,194 @@
+use ethereum_types::H160;
+use rocket::fairing::{Fairing, Info, Kind};
+use rocket::http::ContentType;
+use rocket::request::FromParam;
+use rocket::serde::json::{self, Value};
+use rocket::{Request, Response};
+use std::fmt;
+use std::io::Cursor;
+use std::str::FromStr;
+use tiny_keccak::{Hasher, Keccak};
+
+// an account or contract address in any case, with or without 0x
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Address(H160);
+
+impl Address {
+    // lowercase hex without 0x, as stored in the database
+    pub fn to_db(&self) -> String {
+        self.0
+            .as_bytes()
+            .iter()
+            .map(|byte| format!("{:02x}", byte))
+            .collect()
+    }
+
+    // EIP-55 mixed case with 0x
+    pub fn checksummed(&self) -> String {
+        let hex = self.to_db();
+        let mut hash = [0u8; 32];
+        let mut keccak = Keccak::v256();
+        keccak.update(hex.as_bytes());
+        keccak.finalize(&mut hash);
+        let mixed: String = hex
+            .chars()
+            .enumerate()
+            .map(|(i, c)| {
+                let nibble = (hash[i / 2] >> (if i % 2 == 0 { 4 } else { 0 })) & 0x0f;
+                if nibble >= 8 {
+                    c.to_ascii_uppercase()
+                } else {
+                    c
+                }
+            })
+            .collect();
+        format!("0x{}", mixed)
+    }
+}
+
+impl FromStr for Address {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let hex = s
+            .strip_prefix("0x")
+            .or_else(|| s.strip_prefix("0X"))
+            .unwrap_or(s);
+        if hex.len() != 40 || !hex.bytes().all(|b| b.is_ascii_hexdigit()) {
+            return Err(format!("bad address {}", s));
+        }
+        let bytes: Vec<u8> = (0..40)
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).unwrap())
+            .collect();
+        Ok(Address(H160::from_slice(&bytes)))
+    }
+}
+
+impl fmt::Display for Address {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.to_db())
+    }
+}
+
+impl<'a> FromParam<'a> for Address {
+    type Error = String;
+
+    fn from_param(param: &'a str) -> Result<Self, Self::Error> {
+        param.parse()
+    }
+}
+
+// rewrites address fields of json responses to EIP-55 when asked with ?checksum=true
+pub struct Checksum;
+
+#[rocket::async_trait]
+impl Fairing for Checksum {
+    fn info(&self) -> Info {
+        Info {
+            name: "EIP-55 checksum",
+            kind: Kind::Response,
+        }
+    }
+
+    async fn on_response<'r>(&self, req: &'r Request<'_>, res: &mut Response<'r>) {
+        if !matches!(req.query_value::<bool>("checksum"), Some(Ok(true))) {
+            return;
+        }
+        if res.content_type() != Some(ContentType::JSON) {
+            return;
+        }
+        let body = match res.body_mut().to_string().await {
+            Ok(body) => body,
+            Err(_e) => return,
+        };
+        let body = match json::from_str::<Value>(&body) {
+            Ok(mut value) => {
+                checksum_value(&mut value, false);
+                json::to_string(&value).unwrap_or(body)
+            }
+            Err(_e) => body,
+        };
+        res.set_sized_body(body.len(), Cursor::new(body));
+    }
+}
+
+fn is_address_key(key: &str) -> bool {
+    key.ends_with("address")
+        || matches!(key, "token0" | "token1" | "wrapped_native" | "stable_pool")
+}
+
+fn checksum_value(value: &mut Value, address_key: bool) {
+    match value {
+        Value::String(s) if address_key => {
+            if let Ok(address) = s.parse::<Address>() {
+                *s = address.checksummed();
+            }
+        }
+        Value::Array(values) => {
+            for value in values {
+                checksum_value(value, address_key);
+            }
+        }
+        Value::Object(map) => {
+            for (key, value) in map.iter_mut() {
+                checksum_value(value, is_address_key(key));
+            }
+        }
+        _ => (),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{checksum_value, Address};
+    use rocket::serde::json::{self, Value};
+
+    #[test]
+    fn parse() {
+        let lower: Address = "b4e16d0168e52d35cacd2c6185b44281ec28c9dc".parse().unwrap();
+        let prefixed: Address = "0xB4e16d0168e52d35CaCD2c6185b44281Ec28C9Dc"
+            .parse()
+            .unwrap();
+        assert_eq!(lower, prefixed);
+        assert_eq!(prefixed.to_db(), "b4e16d0168e52d35cacd2c6185b44281ec28c9dc");
+        assert!("0xb4e16d0168e52d35cacd2c6185b44281ec28c9d"
+            .parse::<Address>()
+            .is_err());
+        assert!("0xg4e16d0168e52d35cacd2c6185b44281ec28c9dc"
+            .parse::<Address>()
+            .is_err());
+        assert!("".parse::<Address>().is_err());
+    }
+
+    #[test]
+    fn eip55() {
+        for checksummed in [
+            "0x5aAeb6053F3E94C9b9A09f33669435E7Ef1BeAed",
+            "0xfB6916095ca1df60bB79Ce92cE3Ea74c37c5d359",
+            "0xdbF03B407c01E7cD3CBea99509d93f8DDDC8C6FB",
+            "0xD1220A0cf47c7B9Be7A2E6BA89F429762e7b9aDb",
+        ] {
+            let address: Address = checksummed.to_lowercase().parse().unwrap();
+            assert_eq!(address.checksummed(), checksummed);
+        }
+    }
+
+    #[test]
+    fn checksum_fields() {
+        let mut value: Value = json::from_str(
+            r#"[{"contract_address": "5aaeb6053f3e94c9b9a09f33669435e7ef1beaed", "name": "5aaeb6053f3e94c9b9a09f33669435e7ef1beaed", "coin0": {"token0": "fb6916095ca1df60bb79ce92ce3ea74c37c5d359"}}]"#,
+        )
+        .unwrap();
+        checksum_value(&mut value, false);
+        assert_eq!(
+            value[0]["contract_address"],
+            "0x5aAeb6053F3E94C9b9A09f33669435E7Ef1BeAed"
+        );
+        assert_eq!(value[0]["name"], "5aaeb6053f3e94c9b9a09f33669435e7ef1beaed");
+        assert_eq!(
+            value[0]["coin0"]["token0"],
+            "0xfB6916095ca1df60bB79Ce92cE3Ea74c37c5d359"
+        );
+    }
+}
@@ -0,0 +1,178 @@
+use rocket::fairing::AdHoc;
+use rocket::serde::{Deserialize, Serialize};
+use rocket_db_pools::{sqlx::PgConnection, Database};
+use std::time::Duration;
+
+use crate::models::{
+    alert::{self, ActiveAlert, Alert},
+    block, coin, pool,
+    price::Price,
+    swap,
+};
+use crate::{email, sql, timer, AppConfig};
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(crate = "rocket::serde", default)]
+pub struct AlertConfig {
+    pub poll_secs: u64,
+    // least time between two emails for the same alert
+    pub min_interval_secs: i64,
+    // most blocks scanned per alert and run, bounding rescans while emails keep failing
+    pub max_scan_blocks: u32,
+}
+
+impl Default for AlertConfig {
+    fn default() -> Self {
+        AlertConfig {
+            poll_secs: 60,
+            min_interval_secs: 60 * 60,
+            max_scan_blocks: 10_000,
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct AlertEmail<'a> {
+    symbol: &'a str,
+    quote_symbol: &'a str,
+    direction: &'static str,
+    threshold: String,
+    price: Price,
+    block_number: u32,
+    pool: &'a str,
+    recurring: bool,
+}
+
+pub fn evaluator() -> AdHoc {
+    AdHoc::on_liftoff("Alert evaluator", |rocket| {
+        Box::pin(async move {
+            let db = (**sql::AuthDb::fetch(rocket).unwrap()).clone();
+            let app_config = rocket.state::<AppConfig>().unwrap().clone();
+            rocket::tokio::spawn(async move {
+                let mut interval = rocket::tokio::time::interval(Duration::from_secs(
+                    app_config.alerts.poll_secs.max(1),
+                ));
+                loop {
+                    interval.tick().await;
+                    match db.acquire().await {
+                        Ok(mut conn) => evaluate(&mut conn, &app_config).await,
+                        Err(e) => error!("alert evaluator: {}", e),
+                    }
+                }
+            });
+        })
+    })
+}
+
+// scans swaps since each alert's checked block towards the newest block
+pub async fn evaluate(db: &mut PgConnection, app_config: &AppConfig) {
+    let latest_number: u32 = match block::find_latest(db).await {
+        Some(latest) => (&latest.number).into(),
+        None => return,
+    };
+    for ActiveAlert { alert, email } in alert::find_active(db).await {
+        // new and edited alerts start watching from the newest block
+        let checked_block = match alert.checked_block.map(|number| number as u32) {
+            Some(checked_block) if checked_block < latest_number => checked_block,
+            Some(_) => continue,
+            None => {
+                if let Err(e) = alert::mark_checked(db, &alert.id, latest_number).await {
+                    error!("alert {} check: {}", alert.id, e)
+                }
+                continue;
+            }
+        };
+        // alerts waiting out their interval or a failed email keep their checked block,
+        // so the same swaps are scanned again on the next run
+        let now = (timer::unixtime_ms() / 1000) as i64;
+        if !alert.can_notify(now, app_config.alerts.min_interval_secs) {
+            continue;
+        }
+        let stop_block = latest_number
+            .min(checked_block.saturating_add(app_config.alerts.max_scan_blocks.max(1)));
+        let done = notify_crossing(
+            db,
+            app_config,
+            &alert,
+            &email,
+            checked_block,
+            stop_block,
+            now,
+        )
+        .await;
+        if done {
+            if let Err(e) = alert::mark_checked(db, &alert.id, stop_block).await {
+                error!("alert {} check: {}", alert.id, e)
+            }
+        }
+    }
+}
+
+// true once the swaps up to stop_block need no further look
+async fn notify_crossing(
+    db: &mut PgConnection,
+    app_config: &AppConfig,
+    alert: &Alert,
+    email: &str,
+    start_block: u32,
+    stop_block: u32,
+    now: i64,
+) -> bool {
+    let pool = match pool::find_by_address(db, &alert.pool_contract_address).await {
+        Some(pool) => pool,
+        None => return true,
+    };
+    let (coin0, coin1) = match (
+        coin::find_by_address(db, &pool.token0).await,
+        coin::find_by_address(db, &pool.token1).await,
+    ) {
+        (Some(coin0), Some(coin1)) => (coin0, coin1),
+        _ => return true,
+    };
+    let swaps =
+        swap::find_by_pool_between(db, &pool.contract_address, start_block, stop_block).await;
+    let crossing = swaps.iter().find_map(|swap| {
+        match swap.price(alert.direction(), coin0.decimals, coin1.decimals) {
+            Ok(price) if alert.crossed(&price) => Some((swap.block_number, price)),
+            _ => None,
+        }
+    });
+    let (block_number, price) = match crossing {
+        Some(crossing) => crossing,
+        None => return true,
+    };
+    let (coin, quote_coin) = if alert.direction() {
+        (&coin1, &coin0)
+    } else {
+        (&coin0, &coin1)
+    };
+    let data = AlertEmail {
+        symbol: &coin.symbol,
+        quote_symbol: &quote_coin.symbol,
+        direction: if alert.above { "above" } else { "below" },
+        threshold: alert.price.to_string(),
+        price,
+        block_number,
+        pool: &pool.contract_address,
+        recurring: alert.recurring,
+    };
+    let message = email::build_template_message(
+        "alert",
+        &app_config.from_name,
+        &app_config.from_email,
+        email,
+        &data,
+    );
+    match email::try_send_email(&app_config.smtp, message).await {
+        Ok(_) => {
+            if let Err(e) = alert::mark_notified(db, alert, now).await {
+                error!("alert {} notified: {}", alert.id, e)
+            }
+            true
+        }
+        Err(e) => {
+            error!("alert {} email: {}", alert.id, e);
+            false
+        }
+    }
+}
@@ -0,0 +1,229 @@
+use ethereum_types::{U256, U512};
+
+// keccak of the event signatures and the first four bytes of the call signatures
+pub const SWAP_TOPIC: &str = "0xd78ad95fa46c994b6551d0da85fc275fe613ce37657fb8d5e3d130840159d822"; // Swap(address,uint256,uint256,uint256,uint256,address)
+pub const SYNC_TOPIC: &str = "0x1c411e9a96e071241c2f21f7726b17ae89e3cab4c78be50e062b03a9fffbbad1"; // Sync(uint112,uint112)
+
+// Swap(address,address,int256,int256,uint160,uint128,int24)
+pub const SWAP_V3_TOPIC: &str =
+    "0xc42079f94a6350d7e6235f29174924f928cc2ac818eb64fed8004e115fbcca67";
+pub const FACTORY: &str = "0xc45a0155";
+pub const TOKEN0: &str = "0x0dfe1681";
+pub const TOKEN1: &str = "0xd21220a7";
+pub const NAME: &str = "0x06fdde03";
+pub const SYMBOL: &str = "0x95d89b41";
+pub const DECIMALS: &str = "0x313ce567";
+pub const FEE: &str = "0xddca3f43";
+
+pub struct SwapAmounts {
+    pub in0: U256,
+    pub in1: U256,
+    pub out0: U256,
+    pub out1: U256,
+}
+
+// amounts are signed decimal strings, positive into the pool
+pub struct SwapV3 {
+    pub amount0: String,
+    pub amount1: String,
+    pub sqrt_price_x96: U256,
+    pub liquidity: U256,
+    pub tick: i32,
+}
+
+// lowercase hex without 0x, as stored in the database
+pub fn to_db(hex: &str) -> String {
+    hex.strip_prefix("0x").unwrap_or(hex).to_lowercase()
+}
+
+pub fn to_quantity(number: u32) -> String {
+    format!("0x{:x}", number)
+}
+
+pub fn quantity(hex: &str) -> Result<u64, String> {
+    u64::from_str_radix(hex.strip_prefix("0x").unwrap_or(hex), 16)
+        .map_err(|e| format!("bad quantity {}: {}", hex, e))
+}
+
+pub fn bytes(hex: &str) -> Result<Vec<u8>, String> {
+    let hex = hex.strip_prefix("0x").unwrap_or(hex);
+    if hex.len() % 2 != 0 {
+        return Err(format!("odd length hex {}", hex));
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).map_err(|e| e.to_string()))
+        .collect()
+}
+
+// 32 byte big endian words
+pub fn words(hex: &str) -> Result<Vec<U256>, String> {
+    let bytes = bytes(hex)?;
+    if bytes.len() % 32 != 0 {
+        return Err(format!("data is not whole words, {} bytes", bytes.len()));
+    }
+    Ok(bytes.chunks(32).map(U256::from_big_endian).collect())
+}
+
+// the address in the low 20 bytes of a word, as stored in the database
+pub fn address(word: &str) -> Result<String, String> {
+    let hex = to_db(word);
+    match hex.len() {
+        64 => Ok(hex[24..].to_owned()),
+        _ => Err(format!("bad address word {}", word)),
+    }
+}
+
+pub fn decode_swap(data: &str) -> Result<SwapAmounts, String> {
+    match words(data)?[..] {
+        [in0, in1, out0, out1] => Ok(SwapAmounts {
+            in0,
+            in1,
+            out0,
+            out1,
+        }),
+        _ => Err(format!("bad swap data {}", data)),
+    }
+}
+
+pub fn decode_swap_v3(data: &str) -> Result<SwapV3, String> {
+    match words(data)?[..] {
+        [amount0, amount1, sqrt_price_x96, liquidity, tick] => Ok(SwapV3 {
+            amount0: int(amount0),
+            amount1: int(amount1),
+            sqrt_price_x96,
+            liquidity,
+            tick: int(tick)
+                .parse()
+                .map_err(|_e| format!("bad tick in {}", data))?,
+        }),
+        _ => Err(format!("bad v3 swap data {}", data)),
+    }
+}
+
+// a two's complement word as a signed decimal string
+pub fn int(word: U256) -> String {
+    match word.bit(255) {
+        true => format!("-{}", (!word).overflowing_add(U256::one()).0),
+        false => word.to_string(),
+    }
+}
+
+// the reserves a v2 pool would need for the same liquidity and price,
+// x = L / sqrtP and y = L * sqrtP, None before the pool has a price
+pub fn virtual_reserves(liquidity: U256, sqrt_price_x96: U256) -> Option<(String, String)> {
+    if sqrt_price_x96.is_zero() {
+        return None;
+    }
+    let x = (U512::from(liquidity) << 96) / U512::from(sqrt_price_x96);
+    let y = liquidity.full_mul(sqrt_price_x96) >> 96;
+    Some((x.to_string(), y.to_string()))
+}
+
+pub fn decode_sync(data: &str) -> Result<(U256, U256), String> {
+    match words(data)?[..] {
+        [reserve0, reserve1] => Ok((reserve0, reserve1)),
+        _ => Err(format!("bad sync data {}", data)),
+    }
+}
+
+// an abi string, or the bytes32 some older tokens return for name and symbol
+pub fn decode_string(data: &str) -> Option<String> {
+    let bytes = bytes(data).ok()?;
+    let raw = match bytes.len() {
+        32 => &bytes[..],
+        n if n >= 64 => {
+            let offset = usize::try_from(U256::from_big_endian(&bytes[..32])).ok()?;
+            let start = offset.checked_add(32)?;
+            let len = usize::try_from(U256::from_big_endian(bytes.get(offset..start)?)).ok()?;
+            bytes.get(start..start.checked_add(len)?)?
+        }
+        _ => return None,
+    };
+    let s = String::from_utf8_lossy(raw)
+        .trim_end_matches('\0')
+        .to_owned();
+    Some(s)
+}
+
+#[cfg(test)]
+mod test {
+    use super::{
+        address, decode_string, decode_swap, decode_swap_v3, decode_sync, int, quantity,
+        virtual_reserves,
+    };
+    use ethereum_types::U256;
+
+    #[test]
+    fn quantities() {
+        assert_eq!(quantity("0x13c7a6f"), Ok(20740719));
+        assert_eq!(quantity("0x0"), Ok(0));
+        assert!(quantity("0xz").is_err());
+    }
+
+    #[test]
+    fn addresses() {
+        assert_eq!(
+            address("0x000000000000000000000000b4e16d0168e52d35cacd2c6185b44281ec28c9dc"),
+            Ok("b4e16d0168e52d35cacd2c6185b44281ec28c9dc".to_owned())
+        );
+        assert!(address("0xb4e16d0168e52d35cacd2c6185b44281ec28c9dc").is_err());
+    }
+
+    #[test]
+    fn events() {
+        let swap = decode_swap("0x0000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000017f42dba856bfa8000000000000000000000000000000000000000000000000000000000f18b7770000000000000000000000000000000000000000000000000000000000000000").unwrap();
+        assert_eq!(swap.in0, U256::zero());
+        assert_eq!(swap.in1, U256::from(107878427269709736u64));
+        assert_eq!(swap.out0, U256::from(253278071u64));
+        assert_eq!(swap.out1, U256::zero());
+        let (reserve0, reserve1) = decode_sync("0x00000000000000000000000000000000000000000000000000000e8d4a5100000000000000000000000000000000000000000000000000000de0b6b3a7640000").unwrap();
+        assert_eq!(reserve0, U256::from(16000000000000u64));
+        assert_eq!(reserve1, U256::from(1000000000000000000u64));
+        assert!(decode_sync("0x00").is_err());
+        let swap = decode_swap_v3(V3_SWAP).unwrap();
+        assert_eq!(swap.amount0, "-4000000000");
+        assert_eq!(swap.amount1, "1000000000000000000");
+        assert_eq!(swap.tick, 198079);
+        assert!(decode_swap_v3("0x00").is_err());
+    }
+
+    // amount0 -4000e6, amount1 1e18, sqrtPriceX96 20000 * 2^96, liquidity 1e18, tick 198079
+    const V3_SWAP: &str = "0xffffffffffffffffffffffffffffffffffffffffffffffffffffffff1194d8000000000000000000000000000000000000000000000000000de0b6b3a76400000000000000000000000000000000000000004e200000000000000000000000000000000000000000000000000000000000000000000000000de0b6b3a764000000000000000000000000000000000000000000000000000000000000000305bf";
+
+    #[test]
+    fn signed() {
+        assert_eq!(int(U256::from(5)), "5");
+        assert_eq!(int(U256::MAX), "-1");
+        assert_eq!(int(U256::one() << 255), format!("-{}", U256::one() << 255));
+    }
+
+    #[test]
+    fn v3_reserves() {
+        let sqrt_price_x96 = U256::from(20000) << 96;
+        let liquidity = U256::from(1_000_000_000_000_000_000u64);
+        assert_eq!(
+            virtual_reserves(liquidity, sqrt_price_x96),
+            Some((
+                "50000000000000".to_owned(),
+                "20000000000000000000000".to_owned()
+            ))
+        );
+        assert_eq!(virtual_reserves(liquidity, U256::zero()), None);
+    }
+
+    #[test]
+    fn strings() {
+        // USDC name()
+        assert_eq!(
+            decode_string("0x0000000000000000000000000000000000000000000000000000000000000020000000000000000000000000000000000000000000000000000000000000000855534420436f696e000000000000000000000000000000000000000000000000"),
+            Some("USD Coin".to_owned())
+        );
+        // MKR symbol(), bytes32
+        assert_eq!(
+            decode_string("0x4d4b520000000000000000000000000000000000000000000000000000000000"),
+            Some("MKR".to_owned())
+        );
+        assert_eq!(decode_string("0x"), None);
+    }
+}
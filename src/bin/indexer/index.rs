@@ -0,0 +1,475 @@
+use num_traits::{Signed, Zero};
+use sqlx::types::BigDecimal;
+use sqlx::{Connection, PgConnection};
+use std::collections::{HashMap, HashSet};
+use std::str::FromStr;
+use std::sync::Mutex;
+
+use crate::abi;
+use crate::rpc::{self, RpcBlock, RpcError, RpcLog, Transport};
+use crate::store::{self, NewReserve, NewSwap};
+use crate::IndexerConfig;
+
+#[derive(Debug, PartialEq)]
+pub enum Step {
+    // caught up with the rpc node
+    Idle,
+    // blocks up to and including this one were written
+    Indexed(u32),
+    // a reorg dropped everything after this block
+    Rewound(u32),
+}
+
+pub struct Indexer<'a> {
+    rpc: &'a dyn Transport,
+    config: &'a IndexerConfig,
+    // contracts emitting Swap or Sync that are not pools of the configured factories
+    ignored: Mutex<HashSet<String>>,
+}
+
+impl<'a> Indexer<'a> {
+    pub fn new(rpc: &'a dyn Transport, config: &'a IndexerConfig) -> Indexer<'a> {
+        Indexer {
+            rpc,
+            config,
+            ignored: Mutex::new(HashSet::new()),
+        }
+    }
+
+    // indexes the next batch of blocks after the newest stored one
+    pub async fn step(&self, db: &mut PgConnection) -> Result<Step, String> {
+        let tip = store::tip(db)
+            .await?
+            .filter(|tip| *tip >= self.config.start_block);
+        let from = tip.map_or(self.config.start_block, |tip| tip + 1);
+        let head = rpc::block_number(self.rpc)
+            .await?
+            .saturating_sub(self.config.confirmations);
+        if from > head {
+            return match tip {
+                Some(tip) => self.verify(db, tip, head).await,
+                None => Ok(Step::Idle),
+            };
+        }
+        let to = head.min(from.saturating_add(self.config.batch_blocks.max(1) - 1));
+
+        let mut parent_hash = match tip {
+            Some(tip) => store::block_hash(db, tip).await,
+            None => None,
+        };
+        let mut blocks = vec![];
+        for number in from..=to {
+            let block = rpc::block(self.rpc, number).await?;
+            if let Some(parent_hash) = &parent_hash {
+                if *parent_hash != abi::to_db(&block.parent_hash) {
+                    return match tip {
+                        Some(tip) if number == from => {
+                            self.rewind(db, tip).await.map(Step::Rewound)
+                        }
+                        _ => Err(format!("chain moved under block {}, retrying", number)),
+                    };
+                }
+            }
+            parent_hash = Some(abi::to_db(&block.hash));
+            blocks.push(block);
+        }
+
+        let mut logs = rpc::logs(self.rpc, from, to).await?;
+        logs.retain(|log| !log.removed);
+        let mut by_block: HashMap<u32, Vec<RpcLog>> = HashMap::new();
+        for log in logs {
+            by_block
+                .entry(abi::quantity(&log.block_number)? as u32)
+                .or_default()
+                .push(log);
+        }
+
+        let mut tx = db.begin().await.map_err(|e| e.to_string())?;
+        let mut reserves = HashMap::new();
+        for block in &blocks {
+            let number = abi::quantity(&block.number)? as u32;
+            let mut logs = by_block.remove(&number).unwrap_or_default();
+            logs.sort_by_key(|log| abi::quantity(&log.log_index).unwrap_or(0));
+            for log in &logs {
+                // logs from another fork than the fetched headers
+                if abi::to_db(&log.block_hash) != abi::to_db(&block.hash) {
+                    return Err(format!("logs of block {} changed, retrying", number));
+                }
+                self.apply(&mut tx, &mut reserves, log).await?;
+            }
+            insert_block(&mut tx, block).await?;
+        }
+        tx.commit().await.map_err(|e| e.to_string())?;
+        Ok(Step::Indexed(to))
+    }
+
+    /*
+        rewinds below the parent of the first stored block not linking to the one before it,
+        then compares the newest stored blocks with the node, rewinding below the first that differs
+    */
+    async fn verify(&self, db: &mut PgConnection, tip: u32, head: u32) -> Result<Step, String> {
+        if let Some(number) = store::first_unlinked(db, self.config.verify_depth).await? {
+            let fork = number.saturating_sub(2);
+            store::rewind(db, fork).await?;
+            return Ok(Step::Rewound(fork));
+        }
+        let after = tip
+            .saturating_sub(self.config.verify_depth)
+            .max(self.config.start_block.saturating_sub(1));
+        for (number, hash) in store::block_hashes_after(db, after).await? {
+            if number > head {
+                break;
+            }
+            let remote = rpc::block(self.rpc, number).await?;
+            if abi::to_db(&remote.hash) != hash {
+                let fork = number - 1;
+                store::rewind(db, fork).await?;
+                return Ok(Step::Rewound(fork));
+            }
+        }
+        Ok(Step::Idle)
+    }
+
+    // walks back to the newest block the node still has, then drops the rest
+    async fn rewind(&self, db: &mut PgConnection, tip: u32) -> Result<u32, String> {
+        let mut number = tip;
+        while number >= self.config.start_block && number > 0 {
+            let remote = rpc::block(self.rpc, number).await?;
+            if store::block_hash(db, number).await == Some(abi::to_db(&remote.hash)) {
+                break;
+            }
+            number -= 1;
+        }
+        store::rewind(db, number).await?;
+        Ok(number)
+    }
+
+    async fn apply(
+        &self,
+        db: &mut PgConnection,
+        reserves: &mut HashMap<String, (BigDecimal, BigDecimal)>,
+        log: &RpcLog,
+    ) -> Result<(), String> {
+        let pool = abi::to_db(&log.address);
+        let (token0, token1) = match self.pool(db, &pool).await? {
+            Some(tokens) => tokens,
+            None => return Ok(()),
+        };
+        let block_number = abi::quantity(&log.block_number)? as u32;
+        match log.topics.first().map(|topic| topic.to_lowercase()) {
+            Some(topic) if topic == abi::SYNC_TOPIC => {
+                let (reserve0, reserve1) = abi::decode_sync(&log.data)?;
+                let reserve = NewReserve {
+                    contract_address: &pool,
+                    block_number,
+                    x: reserve0.to_string(),
+                    y: reserve1.to_string(),
+                    sqrt_price_x96: None,
+                    tick: None,
+                    liquidity: None,
+                };
+                store::upsert_reserve(db, &reserve).await?;
+                reserves.insert(pool.clone(), (decimal(&reserve.x)?, decimal(&reserve.y)?));
+            }
+            Some(topic) if topic == abi::SWAP_TOPIC => {
+                let amounts = abi::decode_swap(&log.data)?;
+                let in0 = decimal(&amounts.in0.to_string())?;
+                let in1 = decimal(&amounts.in1.to_string())?;
+                let out0 = decimal(&amounts.out0.to_string())?;
+                let out1 = decimal(&amounts.out1.to_string())?;
+                let swap = NewSwap {
+                    pool_contract_address: &pool,
+                    block_number,
+                    transaction_index: abi::quantity(&log.transaction_index)? as u32,
+                    log_index: abi::quantity(&log.log_index)? as u32,
+                    in0_eth: self.eth_value(db, reserves, &token0, &in0).await,
+                    in1_eth: self.eth_value(db, reserves, &token1, &in1).await,
+                    amount0: &in0 - &out0,
+                    amount1: &in1 - &out1,
+                    in0,
+                    in1,
+                    out0,
+                    out1,
+                };
+                store::insert_swap(db, &swap).await?;
+            }
+            // v3 pools emit no Sync, the swap carries the new pool state
+            Some(topic) if topic == abi::SWAP_V3_TOPIC => {
+                let state = abi::decode_swap_v3(&log.data)?;
+                if let Some((x, y)) = abi::virtual_reserves(state.liquidity, state.sqrt_price_x96) {
+                    let reserve = NewReserve {
+                        contract_address: &pool,
+                        block_number,
+                        x,
+                        y,
+                        sqrt_price_x96: Some(state.sqrt_price_x96.to_string()),
+                        tick: Some(state.tick),
+                        liquidity: Some(state.liquidity.to_string()),
+                    };
+                    store::upsert_reserve(db, &reserve).await?;
+                    reserves.insert(pool.clone(), (decimal(&reserve.x)?, decimal(&reserve.y)?));
+                }
+                let amount0 = decimal(&state.amount0)?;
+                let amount1 = decimal(&state.amount1)?;
+                let (in0, out0) = legs(&amount0);
+                let (in1, out1) = legs(&amount1);
+                let swap = NewSwap {
+                    pool_contract_address: &pool,
+                    block_number,
+                    transaction_index: abi::quantity(&log.transaction_index)? as u32,
+                    log_index: abi::quantity(&log.log_index)? as u32,
+                    in0_eth: self.eth_value(db, reserves, &token0, &in0).await,
+                    in1_eth: self.eth_value(db, reserves, &token1, &in1).await,
+                    in0,
+                    in1,
+                    out0,
+                    out1,
+                    amount0,
+                    amount1,
+                };
+                store::insert_swap(db, &swap).await?;
+            }
+            _ => (),
+        }
+        Ok(())
+    }
+
+    // token0 and token1, recording pools of the configured factories on first sight
+    async fn pool(
+        &self,
+        db: &mut PgConnection,
+        pool: &str,
+    ) -> Result<Option<(String, String)>, String> {
+        if let Some(tokens) = store::find_pool(db, pool).await {
+            return Ok(Some(tokens));
+        }
+        if self
+            .ignored
+            .lock()
+            .map_or(false, |ignored| ignored.contains(pool))
+        {
+            return Ok(None);
+        }
+        // failed calls fail the batch, so a pool is never skipped for a flaky node
+        let factory = match rpc::call(self.rpc, pool, abi::FACTORY).await {
+            Ok(word) => abi::address(&word).ok(),
+            Err(RpcError::Reverted(_)) => None,
+            Err(e) => return Err(e.to_string()),
+        };
+        let protocol = match factory {
+            Some(factory) if factory == self.config.factory => "v2",
+            Some(factory) if factory == self.config.factory_v3 => "v3",
+            Some(_) => {
+                if let Ok(mut ignored) = self.ignored.lock() {
+                    ignored.insert(pool.to_owned());
+                }
+                return Ok(None);
+            }
+            // no factory() to ask, not a pool either
+            None => return Ok(None),
+        };
+        let fee = match protocol {
+            "v3" => abi::words(&rpc::call(self.rpc, pool, abi::FEE).await?)?
+                .first()
+                .map(|fee| fee.low_u32() as i32),
+            _ => None,
+        };
+        let token0 = abi::address(&rpc::call(self.rpc, pool, abi::TOKEN0).await?)?;
+        let token1 = abi::address(&rpc::call(self.rpc, pool, abi::TOKEN1).await?)?;
+        self.coin(db, &token0).await?;
+        self.coin(db, &token1).await?;
+        store::insert_pool(db, pool, &token0, &token1, protocol, fee).await?;
+        Ok(Some((token0, token1)))
+    }
+
+    async fn coin(&self, db: &mut PgConnection, token: &str) -> Result<(), String> {
+        if store::coin_exists(db, token).await {
+            return Ok(());
+        }
+        let decimals = abi::words(&rpc::call(self.rpc, token, abi::DECIMALS).await?)?
+            .first()
+            .map(|decimals| decimals.low_u32() as i32)
+            .ok_or_else(|| format!("no decimals for {}", token))?;
+        // name and symbol are optional in ERC-20
+        let name = match rpc::call(self.rpc, token, abi::NAME).await {
+            Ok(data) => abi::decode_string(&data).unwrap_or_default(),
+            Err(RpcError::Reverted(_)) => "".to_owned(),
+            Err(e) => return Err(e.to_string()),
+        };
+        let symbol = match rpc::call(self.rpc, token, abi::SYMBOL).await {
+            Ok(data) => abi::decode_string(&data).unwrap_or_default(),
+            Err(RpcError::Reverted(_)) => "".to_owned(),
+            Err(e) => return Err(e.to_string()),
+        };
+        store::insert_coin(db, token, &name, &symbol, decimals).await
+    }
+
+    // amount in wei of the wrapped native token, priced by the token's native pool
+    async fn eth_value(
+        &self,
+        db: &mut PgConnection,
+        reserves: &HashMap<String, (BigDecimal, BigDecimal)>,
+        token: &str,
+        amount: &BigDecimal,
+    ) -> Option<BigDecimal> {
+        if amount.is_zero() || token == self.config.wrapped_native {
+            return Some(amount.clone());
+        }
+        let (pool, token_is_0) =
+            store::find_native_pool(db, token, &self.config.wrapped_native).await?;
+        let (reserve0, reserve1) = match reserves.get(&pool) {
+            Some(reserve) => reserve.clone(),
+            None => store::latest_reserve(db, &pool).await?,
+        };
+        let (token_reserve, native_reserve) = match token_is_0 {
+            true => (reserve0, reserve1),
+            false => (reserve1, reserve0),
+        };
+        if token_reserve.is_zero() {
+            return None;
+        }
+        Some((amount * native_reserve / token_reserve).with_scale(0))
+    }
+}
+
+fn decimal(value: &str) -> Result<BigDecimal, String> {
+    BigDecimal::from_str(value).map_err(|e| e.to_string())
+}
+
+// in and out legs of a signed amount
+fn legs(amount: &BigDecimal) -> (BigDecimal, BigDecimal) {
+    match amount.is_positive() {
+        true => (amount.clone(), BigDecimal::zero()),
+        false => (BigDecimal::zero(), -amount),
+    }
+}
+
+async fn insert_block(db: &mut PgConnection, block: &RpcBlock) -> Result<(), String> {
+    store::insert_block(
+        db,
+        abi::quantity(&block.number)? as u32,
+        &abi::to_db(&block.hash),
+        &abi::to_db(&block.parent_hash),
+        abi::quantity(&block.timestamp)? as u32,
+    )
+    .await
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Indexer, Step};
+    use crate::rpc::{mock, Http};
+    use crate::IndexerConfig;
+    use sqlx::types::BigDecimal;
+    use sqlx::{Connection, PgConnection, Row};
+    use std::str::FromStr;
+
+    const POOL: &str = "00000000000000000000000000000000000000a1";
+
+    async fn swaps(db: &mut PgConnection) -> Vec<(i32, BigDecimal, Option<BigDecimal>)> {
+        sqlx::query("select block_number, in0, in0_eth from swaps where pool_contract_address = $1 order by block_number, log_index")
+            .bind(POOL)
+            .fetch_all(db)
+            .await
+            .unwrap()
+            .iter()
+            .map(|row| (row.get("block_number"), row.get("in0"), row.get("in0_eth")))
+            .collect()
+    }
+
+    // chain_a.json ends at 2000000102, chain_b.json replaces 2000000102 and adds 2000000103
+    #[rocket::async_test]
+    async fn index_and_reorg() {
+        let config = IndexerConfig {
+            start_block: 2_000_000_100,
+            ..crate::config().unwrap()
+        };
+        let mut conn = PgConnection::connect(&config.database_url).await.unwrap();
+        let mut tx = conn.begin().await.unwrap();
+
+        let chain_a = Http::new(&mock::serve(include_str!("fixtures/chain_a.json")).await);
+        let indexer = Indexer::new(&chain_a, &config);
+        assert_eq!(
+            indexer.step(&mut tx).await,
+            Ok(Step::Indexed(2_000_000_102))
+        );
+        assert_eq!(indexer.step(&mut tx).await, Ok(Step::Idle));
+        let d = |s: &str| BigDecimal::from_str(s).unwrap();
+        assert_eq!(
+            swaps(&mut tx).await,
+            vec![
+                (
+                    2_000_000_101,
+                    d("100000000000000000000"),
+                    Some(d("45357953630564003"))
+                ),
+                (2_000_000_102, d("0"), Some(d("0"))),
+            ]
+        );
+        let reserve = sqlx::query("select x, y from reserves where contract_address = $1 order by block_number desc limit 1")
+            .bind(POOL)
+            .fetch_one(&mut *tx)
+            .await
+            .unwrap();
+        assert_eq!(reserve.get::<String, &str>("x"), "2078246979513322644076");
+        assert_eq!(reserve.get::<String, &str>("y"), "962517026241844073");
+
+        let chain_b = Http::new(&mock::serve(include_str!("fixtures/chain_b.json")).await);
+        let indexer = Indexer::new(&chain_b, &config);
+        assert_eq!(
+            indexer.step(&mut tx).await,
+            Ok(Step::Rewound(2_000_000_101))
+        );
+        assert_eq!(
+            indexer.step(&mut tx).await,
+            Ok(Step::Indexed(2_000_000_103))
+        );
+        assert_eq!(
+            swaps(&mut tx).await,
+            vec![
+                (
+                    2_000_000_101,
+                    d("100000000000000000000"),
+                    Some(d("45357953630564003"))
+                ),
+                (
+                    2_000_000_102,
+                    d("50000000000000000000"),
+                    Some(d("21637915842780513"))
+                ),
+                (2_000_000_103, d("0"), Some(d("0"))),
+            ]
+        );
+
+        // back on chain_a, found by rechecking stored hashes while idle
+        let indexer = Indexer::new(&chain_a, &config);
+        assert_eq!(
+            indexer.step(&mut tx).await,
+            Ok(Step::Rewound(2_000_000_101))
+        );
+        let reorg = sqlx::query("select fork_block, old_tip, orphaned_swaps, orphaned_reserves from reorgs order by id desc limit 1")
+            .fetch_one(&mut *tx)
+            .await
+            .unwrap();
+        assert_eq!(reorg.get::<i32, &str>("fork_block"), 2_000_000_101);
+        assert_eq!(reorg.get::<i32, &str>("old_tip"), 2_000_000_103);
+        assert_eq!(reorg.get::<i64, &str>("orphaned_swaps"), 2);
+        assert_eq!(reorg.get::<i64, &str>("orphaned_reserves"), 2);
+        assert_eq!(
+            indexer.step(&mut tx).await,
+            Ok(Step::Indexed(2_000_000_102))
+        );
+        assert_eq!(
+            swaps(&mut tx).await,
+            vec![
+                (
+                    2_000_000_101,
+                    d("100000000000000000000"),
+                    Some(d("45357953630564003"))
+                ),
+                (2_000_000_102, d("0"), Some(d("0"))),
+            ]
+        );
+        tx.rollback().await.unwrap();
+    }
+}
@@ -0,0 +1,129 @@
+use figment::providers::{Env, Format, Toml};
+use figment::Figment;
+use log::{error, info, warn, LevelFilter, Log, Metadata, Record};
+use rocket::serde::Deserialize;
+use sqlx::postgres::PgPoolOptions;
+use std::time::Duration;
+use time::{format_description::well_known::Rfc3339, OffsetDateTime};
+
+mod abi;
+mod index;
+mod rpc;
+mod store;
+
+/*
+    Indexer.toml or INDEXER_* environment variables
+
+    database_url = "postgres://localhost/defihub"
+    rpc_url = "http://localhost:8545"
+*/
+#[derive(Deserialize, Debug)]
+#[serde(crate = "rocket::serde", default)]
+pub struct IndexerConfig {
+    pub database_url: String,
+    pub rpc_url: String,
+    // pools of other factories are skipped
+    pub factory: String,
+    pub factory_v3: String,
+    // the token swap amounts are valued in
+    pub wrapped_native: String,
+    // first block indexed on an empty database
+    pub start_block: u32,
+    // blocks fetched and written per transaction
+    pub batch_blocks: u32,
+    // blocks left behind the node's head
+    pub confirmations: u32,
+    // newest stored blocks rechecked against the node while idle
+    pub verify_depth: u32,
+    pub poll_secs: u64,
+}
+
+impl Default for IndexerConfig {
+    fn default() -> Self {
+        IndexerConfig {
+            database_url: "postgres://localhost/defihub".to_owned(),
+            rpc_url: "http://localhost:8545".to_owned(),
+            factory: "5c69bee701ef814a2b6a3edd4b1652cb9cc5aa6f".to_owned(), // Uniswap V2
+            factory_v3: "1f98431c8ad98523631ae4a59f267346ea31f984".to_owned(), // Uniswap V3
+            wrapped_native: "c02aaa39b223fe8d0a0e5c4f27ead9083c756cc2".to_owned(), // WETH
+            start_block: 10000835, // Uniswap V2 factory deployment
+            batch_blocks: 100,
+            confirmations: 0,
+            verify_depth: 12,
+            poll_secs: 12,
+        }
+    }
+}
+
+pub fn config() -> Result<IndexerConfig, figment::Error> {
+    Figment::new()
+        .merge(Toml::file("Indexer.toml"))
+        .merge(Env::prefixed("INDEXER_"))
+        .extract()
+}
+
+// timestamped lines on stderr, info and up
+struct StderrLogger;
+
+impl Log for StderrLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= log::max_level()
+    }
+
+    fn log(&self, record: &Record) {
+        if self.enabled(record.metadata()) {
+            let now = OffsetDateTime::now_utc()
+                .format(&Rfc3339)
+                .unwrap_or_default();
+            eprintln!("{} {} {}", now, record.level(), record.args());
+        }
+    }
+
+    fn flush(&self) {}
+}
+
+static LOGGER: StderrLogger = StderrLogger;
+
+#[rocket::main]
+async fn main() {
+    if log::set_logger(&LOGGER).is_ok() {
+        log::set_max_level(LevelFilter::Info);
+    }
+    let config = match config() {
+        Ok(config) => config,
+        Err(e) => {
+            error!("indexer config: {}", e);
+            std::process::exit(1);
+        }
+    };
+    let db = match PgPoolOptions::new().connect(&config.database_url).await {
+        Ok(db) => db,
+        Err(e) => {
+            error!("indexer database: {}", e);
+            std::process::exit(1);
+        }
+    };
+    if let Err(e) = sqlx::migrate!("./sql").run(&db).await {
+        error!("migration error: {}", e);
+        std::process::exit(1);
+    }
+    let rpc = rpc::Http::new(&config.rpc_url);
+    let indexer = index::Indexer::new(&rpc, &config);
+    let poll = Duration::from_secs(config.poll_secs.max(1));
+    loop {
+        let step = match db.acquire().await {
+            Ok(mut conn) => indexer.step(&mut conn).await,
+            Err(e) => Err(e.to_string()),
+        };
+        match step {
+            // keep going while behind
+            Ok(index::Step::Indexed(to)) => info!("indexed to {}", to),
+            Ok(index::Step::Rewound(to)) => warn!("reorg, rewound to {}", to),
+            Ok(index::Step::Idle) => rocket::tokio::time::sleep(poll).await,
+            Err(e) => {
+                error!("indexer: {}", e);
+                rocket::tokio::time::sleep(poll).await
+            }
+        }
+    }
+}
@@ -0,0 +1,296 @@
+use rocket::serde::json::{json, Value};
+use rocket::serde::{self, Deserialize, DeserializeOwned};
+use std::fmt;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use crate::abi;
+
+#[derive(Deserialize, Debug)]
+#[serde(crate = "rocket::serde", rename_all = "camelCase")]
+pub struct RpcBlock {
+    pub number: String,
+    pub hash: String,
+    pub parent_hash: String,
+    pub timestamp: String,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(crate = "rocket::serde", rename_all = "camelCase")]
+pub struct RpcLog {
+    pub address: String,
+    pub topics: Vec<String>,
+    pub data: String,
+    pub block_number: String,
+    pub block_hash: String,
+    pub transaction_index: String,
+    pub log_index: String,
+    #[serde(default)]
+    pub removed: bool,
+}
+
+// a reverted eth_call is an answer about the contract, any other failure is worth a retry
+#[derive(Debug, PartialEq)]
+pub enum RpcError {
+    Reverted(String),
+    Failed(String),
+}
+
+impl fmt::Display for RpcError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RpcError::Reverted(e) => write!(f, "reverted: {}", e),
+            RpcError::Failed(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl From<RpcError> for String {
+    fn from(e: RpcError) -> Self {
+        e.to_string()
+    }
+}
+
+// one json-rpc round trip, the result or why there is none
+#[rocket::async_trait]
+pub trait Transport: Send + Sync {
+    async fn request(&self, method: &str, params: Value) -> Result<Value, RpcError>;
+}
+
+// geth reports reverts with code 3, other nodes only say so in the message
+fn node_error(method: &str, error: &Value) -> RpcError {
+    let reverted = error["code"].as_i64() == Some(3)
+        || error["message"]
+            .as_str()
+            .map_or(false, |message| message.to_lowercase().contains("revert"));
+    match reverted {
+        true => RpcError::Reverted(format!("{}: {}", method, error)),
+        false => RpcError::Failed(format!("{}: {}", method, error)),
+    }
+}
+
+pub struct Http {
+    client: reqwest::Client,
+    url: String,
+    id: AtomicU64,
+}
+
+impl Http {
+    pub fn new(url: &str) -> Http {
+        Http {
+            client: reqwest::Client::new(),
+            url: url.to_owned(),
+            id: AtomicU64::new(1),
+        }
+    }
+}
+
+#[rocket::async_trait]
+impl Transport for Http {
+    async fn request(&self, method: &str, params: Value) -> Result<Value, RpcError> {
+        let body = json!({
+            "jsonrpc": "2.0",
+            "id": self.id.fetch_add(1, Ordering::Relaxed),
+            "method": method,
+            "params": params,
+        });
+        let response: Value = self
+            .client
+            .post(&self.url)
+            .json(&body)
+            .send()
+            .await
+            .and_then(|response| response.error_for_status())
+            .map_err(|e| RpcError::Failed(format!("{}: {}", method, e)))?
+            .json()
+            .await
+            .map_err(|e| RpcError::Failed(format!("{}: {}", method, e)))?;
+        match (response.get("error"), response.get("result")) {
+            (Some(error), _) if !error.is_null() => Err(node_error(method, error)),
+            (_, Some(result)) => Ok(result.clone()),
+            _ => Err(RpcError::Failed(format!("{}: no result", method))),
+        }
+    }
+}
+
+fn parse<T: DeserializeOwned>(method: &str, value: Value) -> Result<T, String> {
+    serde::json::from_value(value).map_err(|e| format!("{}: {}", method, e))
+}
+
+pub async fn block_number(rpc: &dyn Transport) -> Result<u32, String> {
+    let number: String = parse(
+        "eth_blockNumber",
+        rpc.request("eth_blockNumber", json!([])).await?,
+    )?;
+    Ok(abi::quantity(&number)? as u32)
+}
+
+pub async fn block(rpc: &dyn Transport, number: u32) -> Result<RpcBlock, String> {
+    let block: Option<RpcBlock> = parse(
+        "eth_getBlockByNumber",
+        rpc.request(
+            "eth_getBlockByNumber",
+            json!([abi::to_quantity(number), false]),
+        )
+        .await?,
+    )?;
+    block.ok_or_else(|| format!("block {} not found", number))
+}
+
+// v2 Swap and Sync and v3 Swap logs of every contract, in chain order
+pub async fn logs(rpc: &dyn Transport, from: u32, to: u32) -> Result<Vec<RpcLog>, String> {
+    let filter = json!({
+        "fromBlock": abi::to_quantity(from),
+        "toBlock": abi::to_quantity(to),
+        "topics": [[abi::SWAP_TOPIC, abi::SYNC_TOPIC, abi::SWAP_V3_TOPIC]],
+    });
+    parse(
+        "eth_getLogs",
+        rpc.request("eth_getLogs", json!([filter])).await?,
+    )
+}
+
+// eth_call at the newest block, the returned data as hex
+pub async fn call(rpc: &dyn Transport, to: &str, data: &str) -> Result<String, RpcError> {
+    let call = json!({ "to": format!("0x{}", to), "data": data });
+    parse(
+        "eth_call",
+        rpc.request("eth_call", json!([call, "latest"])).await?,
+    )
+    .map_err(RpcError::Failed)
+}
+
+// a local json-rpc node answering from recorded responses, for tests through Http
+#[cfg(test)]
+pub mod mock {
+    use rocket::config::{LogLevel, Shutdown};
+    use rocket::fairing::AdHoc;
+    use rocket::http::Status;
+    use rocket::serde::json::{self, json, Json, Value};
+    use rocket::State;
+    use std::collections::HashMap;
+    use std::sync::Mutex;
+
+    // recorded results or errors, keyed by method and params
+    pub struct Fixtures(HashMap<String, Value>);
+
+    impl Fixtures {
+        /*
+            [{"method": "eth_blockNumber", "params": [], "result": "0x77359466"},
+             {"method": "eth_call", "params": [...], "error": {"code": 3, "message": "execution reverted"}}, ...]
+        */
+        pub fn load(fixtures: &str) -> Fixtures {
+            let fixtures: Vec<Value> = json::from_str(fixtures).unwrap();
+            Fixtures(
+                fixtures
+                    .into_iter()
+                    .map(|fixture| {
+                        (
+                            Fixtures::key(fixture["method"].as_str().unwrap(), &fixture["params"]),
+                            fixture,
+                        )
+                    })
+                    .collect(),
+            )
+        }
+
+        fn key(method: &str, params: &Value) -> String {
+            format!("{} {}", method, params)
+        }
+    }
+
+    #[rocket::post("/", data = "<request>")]
+    fn node(fixtures: &State<Fixtures>, request: Json<Value>) -> Json<Value> {
+        let key = Fixtures::key(
+            request["method"].as_str().unwrap_or_default(),
+            &request["params"],
+        );
+        Json(match fixtures.0.get(&key) {
+            Some(fixture) if !fixture["error"].is_null() => {
+                json!({ "jsonrpc": "2.0", "id": request["id"], "error": fixture["error"] })
+            }
+            Some(fixture) => {
+                json!({ "jsonrpc": "2.0", "id": request["id"], "result": fixture["result"] })
+            }
+            None => json!({
+                "jsonrpc": "2.0",
+                "id": request["id"],
+                "error": { "code": -32601, "message": format!("no fixture for {}", key) },
+            }),
+        })
+    }
+
+    // a node behind a proxy that is down
+    #[rocket::post("/down")]
+    fn down() -> Status {
+        Status::ServiceUnavailable
+    }
+
+    // the url of a node on a free port, serving until the test's runtime stops
+    pub async fn serve(fixtures: &str) -> String {
+        let config = rocket::Config {
+            port: 0,
+            log_level: LogLevel::Off,
+            shutdown: Shutdown {
+                ctrlc: false,
+                ..Default::default()
+            },
+            ..rocket::Config::debug_default()
+        };
+        let (tx, rx) = rocket::tokio::sync::oneshot::channel();
+        let tx = Mutex::new(Some(tx));
+        let rocket = rocket::custom(config)
+            .manage(Fixtures::load(fixtures))
+            .mount("/", rocket::routes![node, down])
+            .attach(AdHoc::on_liftoff("Port", move |rocket| {
+                let tx = tx.lock().unwrap().take();
+                let port = rocket.config().port;
+                Box::pin(async move {
+                    if let Some(tx) = tx {
+                        let _ = tx.send(port);
+                    }
+                })
+            }));
+        rocket::tokio::spawn(rocket.launch());
+        format!("http://127.0.0.1:{}", rx.await.unwrap())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{block_number, call, mock, Http, RpcError};
+    use crate::abi;
+
+    const POOL: &str = "00000000000000000000000000000000000000a1";
+
+    #[rocket::async_test]
+    async fn http_transport() {
+        let url = mock::serve(
+            r#"[
+                {"method": "eth_blockNumber", "params": [], "result": "0x77359466"},
+                {"method": "eth_call", "params": [{"to": "0x00000000000000000000000000000000000000a1", "data": "0xc45a0155"}, "latest"], "error": {"code": 3, "message": "execution reverted"}},
+                {"method": "eth_call", "params": [{"to": "0x00000000000000000000000000000000000000a1", "data": "0x0dfe1681"}, "latest"], "error": {"code": -32005, "message": "limit exceeded"}}
+            ]"#,
+        )
+        .await;
+        let node = Http::new(&url);
+        assert_eq!(block_number(&node).await, Ok(2_000_000_102));
+        assert!(matches!(
+            call(&node, POOL, abi::FACTORY).await,
+            Err(RpcError::Reverted(_))
+        ));
+        assert!(matches!(
+            call(&node, POOL, abi::TOKEN0).await,
+            Err(RpcError::Failed(_))
+        ));
+        // no fixture
+        assert!(matches!(
+            call(&node, POOL, abi::TOKEN1).await,
+            Err(RpcError::Failed(_))
+        ));
+        let down = Http::new(&format!("{}/down", url));
+        assert!(matches!(
+            call(&down, POOL, abi::FACTORY).await,
+            Err(RpcError::Failed(_))
+        ));
+    }
+}
@@ -0,0 +1,240 @@
+use sqlx::types::BigDecimal;
+use sqlx::{PgConnection, Row};
+
+pub struct NewSwap<'a> {
+    pub pool_contract_address: &'a str,
+    pub block_number: u32,
+    pub transaction_index: u32,
+    pub log_index: u32,
+    pub in0_eth: Option<BigDecimal>,
+    pub in1_eth: Option<BigDecimal>,
+    pub in0: BigDecimal,
+    pub in1: BigDecimal,
+    pub out0: BigDecimal,
+    pub out1: BigDecimal,
+    pub amount0: BigDecimal,
+    pub amount1: BigDecimal,
+}
+
+pub struct NewReserve<'a> {
+    pub contract_address: &'a str,
+    pub block_number: u32,
+    pub x: String,
+    pub y: String,
+    // v3 pool state, x and y then hold its virtual reserves
+    pub sqrt_price_x96: Option<String>,
+    pub tick: Option<i32>,
+    pub liquidity: Option<String>,
+}
+
+// newest indexed block
+pub async fn tip(db: &mut PgConnection) -> Result<Option<u32>, String> {
+    match sqlx::query("select max(number) as number from blocks")
+        .fetch_one(db)
+        .await
+    {
+        Ok(row) => Ok(row
+            .get::<Option<i32>, &str>("number")
+            .map(|number| number as u32)),
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+// stored hashes after block_number, oldest first
+pub async fn block_hashes_after(
+    db: &mut PgConnection,
+    block_number: u32,
+) -> Result<Vec<(u32, String)>, String> {
+    match sqlx::query("select number, hash from blocks where number > $1 order by number")
+        .bind(block_number as i32)
+        .fetch_all(db)
+        .await
+    {
+        Ok(rows) => Ok(rows
+            .iter()
+            .map(|row| (row.get::<i32, &str>("number") as u32, row.get("hash")))
+            .collect()),
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+pub async fn block_hash(db: &mut PgConnection, number: u32) -> Option<String> {
+    match sqlx::query("select hash from blocks where number = $1")
+        .bind(number as i32)
+        .fetch_one(db)
+        .await
+    {
+        Ok(row) => Some(row.get("hash")),
+        Err(_e) => None,
+    }
+}
+
+pub async fn insert_block(
+    db: &mut PgConnection,
+    number: u32,
+    hash: &str,
+    parent_hash: &str,
+    timestamp: u32,
+) -> Result<(), String> {
+    sqlx::query("insert into blocks (number, hash, parent_hash, timestamp) values ($1, $2, $3, $4)")
+        .bind(number as i32)
+        .bind(hash)
+        .bind(parent_hash)
+        .bind(timestamp as i32)
+        .execute(db)
+        .await
+        .map(|_| ())
+        .map_err(|e| e.to_string())
+}
+
+pub async fn insert_swap(db: &mut PgConnection, swap: &NewSwap<'_>) -> Result<(), String> {
+    sqlx::query("insert into swaps (pool_contract_address, block_number, transaction_index, log_index, in0_eth, in1_eth, in0, in1, out0, out1, amount0, amount1) values ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12)")
+        .bind(swap.pool_contract_address)
+        .bind(swap.block_number as i32)
+        .bind(swap.transaction_index as i32)
+        .bind(swap.log_index as i32)
+        .bind(&swap.in0_eth)
+        .bind(&swap.in1_eth)
+        .bind(&swap.in0)
+        .bind(&swap.in1)
+        .bind(&swap.out0)
+        .bind(&swap.out1)
+        .bind(&swap.amount0)
+        .bind(&swap.amount1)
+        .execute(db)
+        .await
+        .map(|_| ())
+        .map_err(|e| e.to_string())
+}
+
+// the last Sync or v3 Swap of a block wins
+pub async fn upsert_reserve(db: &mut PgConnection, reserve: &NewReserve<'_>) -> Result<(), String> {
+    sqlx::query("insert into reserves (contract_address, block_number, x, y, sqrt_price_x96, tick, liquidity) values ($1, $2, $3, $4, $5, $6, $7) on conflict (contract_address, block_number) do update set x = excluded.x, y = excluded.y, sqrt_price_x96 = excluded.sqrt_price_x96, tick = excluded.tick, liquidity = excluded.liquidity")
+        .bind(reserve.contract_address)
+        .bind(reserve.block_number as i32)
+        .bind(&reserve.x)
+        .bind(&reserve.y)
+        .bind(&reserve.sqrt_price_x96)
+        .bind(reserve.tick)
+        .bind(&reserve.liquidity)
+        .execute(db)
+        .await
+        .map(|_| ())
+        .map_err(|e| e.to_string())
+}
+
+pub async fn latest_reserve(
+    db: &mut PgConnection,
+    contract_address: &str,
+) -> Option<(BigDecimal, BigDecimal)> {
+    match sqlx::query("select x::numeric as x, y::numeric as y from reserves where contract_address = $1 order by block_number desc limit 1")
+        .bind(contract_address)
+        .fetch_one(db)
+        .await
+    {
+        Ok(row) => Some((row.get("x"), row.get("y"))),
+        Err(_e) => None,
+    }
+}
+
+// token0 and token1 of a known pool
+pub async fn find_pool(db: &mut PgConnection, contract_address: &str) -> Option<(String, String)> {
+    match sqlx::query("select token0, token1 from pools where contract_address = $1")
+        .bind(contract_address)
+        .fetch_one(db)
+        .await
+    {
+        Ok(row) => Some((row.get("token0"), row.get("token1"))),
+        Err(_e) => None,
+    }
+}
+
+// the pool pairing token with the wrapped native token holding the most of it in its latest
+// reserve, and whether token is token0
+pub async fn find_native_pool(
+    db: &mut PgConnection,
+    token: &str,
+    wrapped_native: &str,
+) -> Option<(String, bool)> {
+    match sqlx::query("select pools.contract_address, pools.token0 = $1 as token_is_0 from pools left join lateral (select x, y from reserves where reserves.contract_address = pools.contract_address order by block_number desc limit 1) latest on true where (token0 = $1 and token1 = $2) or (token0 = $2 and token1 = $1) order by (case when token0 = $2 then latest.x else latest.y end)::numeric desc nulls last, pools.contract_address limit 1")
+        .bind(token)
+        .bind(wrapped_native)
+        .fetch_one(db)
+        .await
+    {
+        Ok(row) => Some((row.get("contract_address"), row.get("token_is_0"))),
+        Err(_e) => None,
+    }
+}
+
+pub async fn insert_pool(
+    db: &mut PgConnection,
+    contract_address: &str,
+    token0: &str,
+    token1: &str,
+    protocol: &str,
+    fee: Option<i32>,
+) -> Result<(), String> {
+    sqlx::query("insert into pools (contract_address, token0, token1, protocol, fee) values ($1, $2, $3, $4, $5) on conflict do nothing")
+        .bind(contract_address)
+        .bind(token0)
+        .bind(token1)
+        .bind(protocol)
+        .bind(fee)
+        .execute(db)
+        .await
+        .map(|_| ())
+        .map_err(|e| e.to_string())
+}
+
+pub async fn coin_exists(db: &mut PgConnection, contract_address: &str) -> bool {
+    sqlx::query("select 1 from coins where contract_address = $1")
+        .bind(contract_address)
+        .fetch_optional(db)
+        .await
+        .map(|row| row.is_some())
+        .unwrap_or(false)
+}
+
+pub async fn insert_coin(
+    db: &mut PgConnection,
+    contract_address: &str,
+    name: &str,
+    symbol: &str,
+    decimals: i32,
+) -> Result<(), String> {
+    sqlx::query("insert into coins (contract_address, name, symbol, decimals) values ($1, $2, $3, $4) on conflict do nothing")
+        .bind(contract_address)
+        .bind(name)
+        .bind(symbol)
+        .bind(decimals)
+        .execute(db)
+        .await
+        .map(|_| ())
+        .map_err(|e| e.to_string())
+}
+
+// drops everything after block_number, rollups from the hour of the first dropped block,
+// and records the reorg
+pub async fn rewind(db: &mut PgConnection, block_number: u32) -> Result<(), String> {
+    sqlx::query("select rewind_blocks($1)")
+        .bind(block_number as i32)
+        .execute(db)
+        .await
+        .map(|_| ())
+        .map_err(|e| e.to_string())
+}
+
+// the first of the newest depth blocks not linking to the stored block before it
+pub async fn first_unlinked(db: &mut PgConnection, depth: u32) -> Result<Option<u32>, String> {
+    match sqlx::query("select first_unlinked_block($1) as number")
+        .bind(depth as i32)
+        .fetch_one(db)
+        .await
+    {
+        Ok(row) => Ok(row
+            .get::<Option<i32>, &str>("number")
+            .map(|number| number as u32)),
+        Err(e) => Err(e.to_string()),
+    }
+}
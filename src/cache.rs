@@ -0,0 +1,151 @@
+use rocket::http::{Header, Status};
+use rocket::outcome::Outcome;
+use rocket::request::{self, FromRequest};
+use rocket::response::{self, Responder};
+use rocket::serde::json::Value;
+use rocket::{Request, Response};
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+// one /pools/top result, the window resolved to blocks
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct TopKey {
+    pub chain: String,
+    pub start_block: u32,
+    pub stop_block: u32,
+    pub stop_hash: String,
+}
+
+impl TopKey {
+    pub fn etag(&self) -> String {
+        format!(
+            "\"top-{}-{}-{}-{}\"",
+            self.chain, self.start_block, self.stop_block, self.stop_hash
+        )
+    }
+}
+
+// top pools by key, entries behind a chain's newest block or on an orphaned one are dropped
+#[derive(Default)]
+pub struct TopCache {
+    entries: Mutex<HashMap<TopKey, Value>>,
+}
+
+impl TopCache {
+    pub fn get(&self, key: &TopKey) -> Option<Value> {
+        self.entries.lock().ok()?.get(key).cloned()
+    }
+
+    pub fn insert(&self, key: TopKey, pools: Value) {
+        if let Ok(mut entries) = self.entries.lock() {
+            entries.retain(|held, _| {
+                held.chain != key.chain
+                    || held.stop_block > key.stop_block
+                    || held.stop_hash == key.stop_hash
+            });
+            entries.insert(key, pools);
+        }
+    }
+}
+
+// the If-None-Match header, if any
+pub struct IfNoneMatch(Option<String>);
+
+impl IfNoneMatch {
+    pub fn matches(&self, etag: &str) -> bool {
+        match &self.0 {
+            Some(header) => header
+                .split(',')
+                .map(str::trim)
+                .any(|tag| tag == "*" || tag.strip_prefix("W/").unwrap_or(tag) == etag),
+            None => false,
+        }
+    }
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for IfNoneMatch {
+    type Error = ();
+
+    async fn from_request(req: &'r Request<'_>) -> request::Outcome<Self, Self::Error> {
+        Outcome::Success(IfNoneMatch(
+            req.headers().get_one("If-None-Match").map(str::to_owned),
+        ))
+    }
+}
+
+// body with ETag and Cache-Control, a bare 304 when body is None
+pub struct Cached<R> {
+    pub etag: String,
+    pub max_age: u64,
+    pub body: Option<R>,
+}
+
+impl<'r, 'o: 'r, R: Responder<'r, 'o>> Responder<'r, 'o> for Cached<R> {
+    fn respond_to(self, req: &'r Request<'_>) -> response::Result<'o> {
+        let mut res = match self.body {
+            Some(body) => Response::build_from(body.respond_to(req)?).finalize(),
+            None => Response::build().status(Status::NotModified).finalize(),
+        };
+        res.set_header(Header::new("ETag", self.etag));
+        res.set_header(Header::new(
+            "Cache-Control",
+            format!("public, max-age={}", self.max_age),
+        ));
+        Ok(res)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{IfNoneMatch, TopCache, TopKey};
+    use rocket::serde::json::json;
+
+    #[test]
+    fn if_none_match() {
+        let etag = "\"top-ethereum-1-2-ab\"";
+        assert!(IfNoneMatch(Some(etag.to_owned())).matches(etag));
+        assert!(IfNoneMatch(Some(format!("\"other\", W/{}", etag))).matches(etag));
+        assert!(IfNoneMatch(Some("*".to_owned())).matches(etag));
+        assert!(!IfNoneMatch(Some("\"top-ethereum-1-3-ab\"".to_owned())).matches(etag));
+        assert!(!IfNoneMatch(None).matches(etag));
+    }
+
+    #[test]
+    fn newer_block_invalidates() {
+        let key = |chain: &str, start_block, stop_block| TopKey {
+            chain: chain.to_owned(),
+            start_block,
+            stop_block,
+            stop_hash: format!("{:064x}", stop_block),
+        };
+        let cache = TopCache::default();
+        cache.insert(key("ethereum", 1, 10), json!([1]));
+        cache.insert(key("ethereum", 5, 10), json!([2]));
+        cache.insert(key("base", 1, 10), json!([3]));
+        assert_eq!(cache.get(&key("ethereum", 1, 10)), Some(json!([1])));
+
+        cache.insert(key("ethereum", 2, 11), json!([4]));
+        assert_eq!(cache.get(&key("ethereum", 1, 10)), None);
+        assert_eq!(cache.get(&key("ethereum", 5, 10)), None);
+        assert_eq!(cache.get(&key("ethereum", 2, 11)), Some(json!([4])));
+        assert_eq!(cache.get(&key("base", 1, 10)), Some(json!([3])));
+    }
+
+    #[test]
+    fn reorged_block_misses() {
+        let key = |stop_hash: &str| TopKey {
+            chain: "ethereum".to_owned(),
+            start_block: 1,
+            stop_block: 10,
+            stop_hash: stop_hash.to_owned(),
+        };
+        let cache = TopCache::default();
+        cache.insert(key("aa"), json!([1]));
+        assert_eq!(cache.get(&key("bb")), None);
+        assert_ne!(key("aa").etag(), key("bb").etag());
+        cache.insert(key("bb"), json!([2]));
+        assert_eq!(cache.get(&key("aa")), None);
+        assert_eq!(cache.get(&key("bb")), Some(json!([2])));
+    }
+}
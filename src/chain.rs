@@ -0,0 +1,214 @@
+use rocket::fairing::AdHoc;
+use rocket::http::Status;
+use rocket::outcome::{try_outcome, Outcome};
+use rocket::request::{self, FromRequest};
+use rocket::serde::{Deserialize, Serialize};
+use rocket::Request;
+use rocket_db_pools::sqlx::{
+    pool::PoolConnection, postgres::PgPoolOptions, PgConnection, PgPool, Postgres,
+};
+use rocket_db_pools::Database;
+use std::collections::HashMap;
+use std::ops::{Deref, DerefMut};
+
+use crate::models::coin::{self, CashConfig};
+use crate::{sql, AppConfig};
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+#[serde(crate = "rocket::serde", default)]
+pub struct ChainConfig {
+    // the <chain> in /chains/<chain>/... routes
+    pub name: String,
+    pub chain_id: u64,
+    // average seconds between blocks
+    pub block_time_secs: u64,
+    // the token behind the *_eth swap amounts
+    pub wrapped_native: String,
+    // wrapped native/stable pool tried first for the native usd price
+    pub stable_pool: String,
+    // extra chains only, the default chain reads from auth_db
+    #[serde(skip_serializing)]
+    pub database_url: Option<String>,
+    // falls back to the top level cash section
+    #[serde(skip_serializing)]
+    pub cash: Option<CashConfig>,
+}
+
+impl Default for ChainConfig {
+    fn default() -> Self {
+        ChainConfig {
+            name: "ethereum".to_owned(),
+            chain_id: 1,
+            block_time_secs: 12,
+            wrapped_native: coin::WETH.to_owned(),
+            stable_pool: "b4e16d0168e52d35cacd2c6185b44281ec28c9dc".to_owned(), // USDC/WETH
+            database_url: None,
+            cash: None,
+        }
+    }
+}
+
+pub struct Chain {
+    pub config: ChainConfig,
+    pub cash: CashConfig,
+    db: PgPool,
+}
+
+impl Chain {
+    pub fn pool(&self) -> &PgPool {
+        &self.db
+    }
+}
+
+// every served chain by name, built once the auth_db pool is up
+pub struct Chains {
+    default: String,
+    chains: HashMap<String, Chain>,
+}
+
+impl Chains {
+    pub fn get(&self, name: &str) -> Option<&Chain> {
+        self.chains.get(name)
+    }
+
+    pub fn default_chain(&self) -> &Chain {
+        &self.chains[&self.default]
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Chain> {
+        self.chains.values()
+    }
+
+    pub fn configs(&self) -> Vec<&ChainConfig> {
+        let mut configs: Vec<&ChainConfig> =
+            self.chains.values().map(|chain| &chain.config).collect();
+        configs.sort_by_key(|config| config.chain_id);
+        configs
+    }
+}
+
+pub fn registry() -> AdHoc {
+    AdHoc::try_on_ignite("Chain registry", |rocket| async move {
+        let app_config = match rocket.figment().extract::<AppConfig>() {
+            Ok(app_config) => app_config,
+            Err(e) => {
+                error!("chain registry config: {}", e);
+                return Err(rocket);
+            }
+        };
+        let auth_db = match sql::AuthDb::fetch(&rocket) {
+            Some(db) => (**db).clone(),
+            None => return Err(rocket),
+        };
+        let mut chains = HashMap::new();
+        chains.insert(
+            app_config.chain.name.clone(),
+            Chain {
+                cash: app_config
+                    .chain
+                    .cash
+                    .clone()
+                    .unwrap_or_else(|| app_config.cash.clone()),
+                config: app_config.chain.clone(),
+                db: auth_db,
+            },
+        );
+        for config in app_config.chains {
+            if chains.contains_key(&config.name) {
+                error!("chain {} configured twice", config.name);
+                return Err(rocket);
+            }
+            let db = match config
+                .database_url
+                .as_deref()
+                .map(|url| PgPoolOptions::new().connect_lazy(url))
+            {
+                Some(Ok(db)) => db,
+                Some(Err(e)) => {
+                    error!("chain {} database: {}", config.name, e);
+                    return Err(rocket);
+                }
+                None => {
+                    error!("chain {} has no database_url", config.name);
+                    return Err(rocket);
+                }
+            };
+            chains.insert(
+                config.name.clone(),
+                Chain {
+                    cash: config
+                        .cash
+                        .clone()
+                        .unwrap_or_else(|| app_config.cash.clone()),
+                    config,
+                    db,
+                },
+            );
+        }
+        Ok(rocket.manage(Chains {
+            default: app_config.chain.name,
+            chains,
+        }))
+    })
+}
+
+// the chain named by /chains/<chain>/..., the default chain elsewhere
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for &'r Chain {
+    type Error = &'static str;
+
+    async fn from_request(req: &'r Request<'_>) -> request::Outcome<Self, Self::Error> {
+        let chains = match req.rocket().state::<Chains>() {
+            Some(chains) => chains,
+            None => return Outcome::Error((Status::InternalServerError, "no chains")),
+        };
+        match req.routed_segment(0) {
+            Some("chains") => match req.routed_segment(1).and_then(|name| chains.get(name)) {
+                Some(chain) => Outcome::Success(chain),
+                None => Outcome::Error((Status::NotFound, "unknown chain")),
+            },
+            _ => Outcome::Success(chains.default_chain()),
+        }
+    }
+}
+
+// a connection to the request's chain
+pub struct ChainDb<'r> {
+    pub chain: &'r Chain,
+    conn: PoolConnection<Postgres>,
+}
+
+impl Deref for ChainDb<'_> {
+    type Target = PgConnection;
+
+    fn deref(&self) -> &Self::Target {
+        &self.conn
+    }
+}
+
+impl DerefMut for ChainDb<'_> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.conn
+    }
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for ChainDb<'r> {
+    type Error = &'static str;
+
+    async fn from_request(req: &'r Request<'_>) -> request::Outcome<Self, Self::Error> {
+        let chain = try_outcome!(req.guard::<&Chain>().await);
+        match chain.db.acquire().await {
+            Ok(conn) => Outcome::Success(ChainDb { chain, conn }),
+            Err(_e) => Outcome::Error((Status::ServiceUnavailable, "chain database unavailable")),
+        }
+    }
+}
+
+// chain scoped responses carry the chain id next to the data
+#[derive(Serialize)]
+#[serde(crate = "rocket::serde")]
+pub struct Tagged<T> {
+    pub chain_id: u64,
+    pub data: T,
+}
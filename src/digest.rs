@@ -0,0 +1,237 @@
+use hmac::{Hmac, Mac};
+use rocket::fairing::AdHoc;
+use rocket::serde::{Deserialize, Serialize};
+use rocket_db_pools::{sqlx::PgConnection, Database};
+use sha2::Sha256;
+use std::time::Duration;
+use time::{OffsetDateTime, Time};
+
+use crate::chain::{ChainConfig, Chains};
+use crate::models::{block, coin::CashConfig, pool::Pool, price::Price, reserve, watchlist};
+use crate::time::Since;
+use crate::{email, sql, AppConfig};
+
+type HmacSha256 = Hmac<Sha256>;
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(crate = "rocket::serde", default)]
+pub struct DigestConfig {
+    // utc hour the digest goes out
+    pub hour: u8,
+    // signs unsubscribe links, the digest is off while empty
+    pub secret: String,
+    // unsubscribe link prefix, followed by <account_id>/<signature>
+    pub unsubscribe_url: String,
+}
+
+impl Default for DigestConfig {
+    fn default() -> Self {
+        DigestConfig {
+            hour: 8,
+            secret: "".to_owned(),
+            unsubscribe_url: "http://localhost:8000/digest/unsubscribe/".to_owned(),
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct DigestPool {
+    pair: String,
+    pool: String,
+    volume_usd: Option<String>,
+    price: Option<Price>,
+    price_change: Option<String>,
+    reserve_stddev: Option<String>,
+}
+
+#[derive(Serialize)]
+struct DigestEmail<'a> {
+    watched: Vec<DigestPool>,
+    top: &'a [DigestPool],
+    unsubscribe_url: String,
+}
+
+pub fn scheduler() -> AdHoc {
+    AdHoc::on_liftoff("Digest scheduler", |rocket| {
+        Box::pin(async move {
+            let app_config = rocket.state::<AppConfig>().unwrap().clone();
+            if app_config.digest.secret.is_empty() {
+                warn!("digest secret not set, daily digest disabled");
+                return;
+            }
+            let db = (**sql::AuthDb::fetch(rocket).unwrap()).clone();
+            // auth_db holds the default chain's pools, priced with its resolved cash tokens
+            let chain = match rocket.state::<Chains>() {
+                Some(chains) => chains.default_chain(),
+                None => {
+                    error!("digest: no chains configured, daily digest disabled");
+                    return;
+                }
+            };
+            let (chain, cash) = (chain.config.clone(), chain.cash.clone());
+            rocket::tokio::spawn(async move {
+                loop {
+                    let wait = secs_until_hour(OffsetDateTime::now_utc(), app_config.digest.hour);
+                    rocket::tokio::time::sleep(Duration::from_secs(wait)).await;
+                    match db.acquire().await {
+                        Ok(mut conn) => send_digests(&mut conn, &app_config, &chain, &cash).await,
+                        Err(e) => error!("digest: {}", e),
+                    }
+                }
+            });
+        })
+    })
+}
+
+pub async fn send_digests(
+    db: &mut PgConnection,
+    app_config: &AppConfig,
+    chain: &ChainConfig,
+    cash: &CashConfig,
+) {
+    let latest_block = match block::find_latest(db).await {
+        Some(block) => block,
+        None => return,
+    };
+    let start_block = block::find_since(db, &latest_block, &Since::default()).await;
+    let top = sql::top_pools(db, &start_block, &latest_block.number, chain, cash).await;
+    let top = digest_pools(db, &top, &start_block).await;
+    for account in sql::find_digest_subscribers(db).await {
+        let pool_contract_addresses = watchlist::find_by_account(db, &account.id).await;
+        let watched = sql::pools_with_sums(
+            db,
+            &pool_contract_addresses,
+            &start_block,
+            &latest_block.number,
+            chain,
+            cash,
+        )
+        .await;
+        let data = DigestEmail {
+            watched: digest_pools(db, &watched, &start_block).await,
+            top: &top,
+            unsubscribe_url: format!(
+                "{}{}/{}",
+                app_config.digest.unsubscribe_url,
+                account.id,
+                sign(&app_config.digest.secret, &account.id)
+            ),
+        };
+        let message = email::build_template_message(
+            "digest",
+            &app_config.from_name,
+            &app_config.from_email,
+            &account.email,
+            &data,
+        );
+        if let Err(e) = email::try_send_email(&app_config.smtp, message).await {
+            error!("digest {} email: {}", account.id, e);
+        }
+    }
+}
+
+async fn digest_pools(
+    db: &mut PgConnection,
+    pools: &[Pool],
+    start_block: &block::Number,
+) -> Vec<DigestPool> {
+    let mut r = vec![];
+    for pool in pools {
+        let (coin0, coin1) = match (&pool.coin0, &pool.coin1) {
+            (Some(coin0), Some(coin1)) => (coin0, coin1),
+            _ => continue,
+        };
+        let price = pool
+            .reserve
+            .as_ref()
+            .and_then(|reserve| reserve.price(coin0.decimals, coin1.decimals));
+        let start_price =
+            reserve::find_by_address_at(db, &pool.contract_address, start_block.into())
+                .await
+                .and_then(|reserve| reserve.price(coin0.decimals, coin1.decimals));
+        let price_change = match (&price, &start_price) {
+            (Some(price), Some(start_price)) => percent_change(start_price, price),
+            _ => None,
+        };
+        r.push(DigestPool {
+            pair: format!("{}/{}", coin1.symbol, coin0.symbol),
+            pool: pool.contract_address.clone(),
+            volume_usd: pool.volume_usd.map(|usd| format!("{:.2}", usd)),
+            price,
+            price_change: price_change.map(|change| format!("{:.2}", change)),
+            reserve_stddev: pool
+                .reserve_summary
+                .as_ref()
+                .and_then(|summary| summary.stddev.as_ref())
+                .map(|stddev| stddev.to_string()),
+        })
+    }
+    r
+}
+
+fn percent_change(from: &Price, to: &Price) -> Option<f64> {
+    let from = from.to_f64()?;
+    match from == 0.0 {
+        true => None,
+        false => Some((to.to_f64()? - from) / from * 100.0),
+    }
+}
+
+// unsubscribe links carry this so they work without a login
+pub fn sign(secret: &str, account_id: &str) -> String {
+    let mut mac = HmacSha256::new_from_slice(secret.as_bytes()).unwrap();
+    mac.update(account_id.as_bytes());
+    bs58::encode(mac.finalize().into_bytes()).into_string()
+}
+
+pub fn verify(secret: &str, account_id: &str, signature: &str) -> bool {
+    let signature = match bs58::decode(signature).into_vec() {
+        Ok(signature) => signature,
+        Err(_e) => return false,
+    };
+    let mut mac = HmacSha256::new_from_slice(secret.as_bytes()).unwrap();
+    mac.update(account_id.as_bytes());
+    !secret.is_empty() && mac.verify_slice(&signature).is_ok()
+}
+
+fn secs_until_hour(now: OffsetDateTime, hour: u8) -> u64 {
+    let today = now.replace_time(Time::from_hms(hour % 24, 0, 0).unwrap());
+    let next = if today > now {
+        today
+    } else {
+        today + time::Duration::days(1)
+    };
+    (next - now).whole_seconds() as u64
+}
+
+#[cfg(test)]
+mod test {
+    use super::{secs_until_hour, sign, verify};
+    use time::macros::datetime;
+
+    #[test]
+    fn signature_roundtrip() {
+        let signature = sign("secret", "account");
+        assert!(verify("secret", "account", &signature));
+        assert!(!verify("secret", "other-account", &signature));
+        assert!(!verify("other-secret", "account", &signature));
+        assert!(!verify("secret", "account", "not-base58-0OIl"));
+        assert!(!verify("", "account", &sign("", "account")));
+    }
+
+    #[test]
+    fn next_hour() {
+        assert_eq!(
+            secs_until_hour(datetime!(2024-09-15 07:30:00 UTC), 8),
+            30 * 60
+        );
+        assert_eq!(
+            secs_until_hour(datetime!(2024-09-15 08:00:00 UTC), 8),
+            24 * 60 * 60
+        );
+        assert_eq!(
+            secs_until_hour(datetime!(2024-09-15 09:00:00 UTC), 8),
+            23 * 60 * 60
+        );
+    }
+}
@@ -1,6 +1,7 @@
 use crate::models::account::Account;
 use handlebars::Handlebars;
 use mail_send::{self, mail_builder::MessageBuilder, SmtpClientBuilder};
+use rocket::serde::Serialize;
 use std::collections::HashMap;
 
 pub fn build_message<'b>(
@@ -9,43 +10,71 @@ pub fn build_message<'b>(
     account: &'b Account,
     url: &'b str,
 ) -> MessageBuilder<'b> {
+    let mut data = HashMap::new();
+    data.insert("url", url);
+    let (subject, body) = render("register", &data);
+
+    MessageBuilder::new()
+        .from((from_name, from_email))
+        .to(account.email.as_str())
+        .subject(subject)
+        .text_body(body)
+}
+
+// message from emails/<name>_subject.hbs and emails/<name>_body.hbs
+pub fn build_template_message<'b, T: Serialize>(
+    name: &str,
+    from_name: &'b str,
+    from_email: &'b str,
+    to_email: &'b str,
+    data: &T,
+) -> MessageBuilder<'b> {
+    let (subject, body) = render(name, data);
+
+    MessageBuilder::new()
+        .from((from_name, from_email))
+        .to(to_email)
+        .subject(subject)
+        .text_body(body)
+}
+
+fn render<T: Serialize>(name: &str, data: &T) -> (String, String) {
     let mut handlebars = Handlebars::new();
     handlebars.register_escape_fn(handlebars::no_escape);
+    let body_name = format!("{}_body", name);
     handlebars
-        .register_template_file("register_body", "emails/register_body.hbs")
+        .register_template_file(&body_name, format!("emails/{}.hbs", body_name))
         .unwrap();
-    let mut data = HashMap::new();
-    data.insert("url", url);
-    let body = handlebars.render("register_body", &data).unwrap();
+    let body = handlebars.render(&body_name, data).unwrap();
 
+    let subject_name = format!("{}_subject", name);
     handlebars
-        .register_template_file("register_subject", "emails/register_subject.hbs")
+        .register_template_file(&subject_name, format!("emails/{}.hbs", subject_name))
         .unwrap();
-    let data: HashMap<&str, &str> = HashMap::new();
     let subject = handlebars
-        .render("register_subject", &data)
+        .render(&subject_name, data)
         .unwrap()
         .lines()
         .next() // first line only
         .unwrap()
         .to_string();
-
-    MessageBuilder::new()
-        .from((from_name, from_email))
-        .to(account.email.as_str())
-        .subject(subject)
-        .text_body(body)
+    (subject, body)
 }
 
 pub async fn send_email<'b>(smtp_host: &str, email: MessageBuilder<'b>) {
+    try_send_email(smtp_host, email).await.unwrap();
+}
+
+pub async fn try_send_email<'b>(
+    smtp_host: &str,
+    email: MessageBuilder<'b>,
+) -> mail_send::Result<()> {
     println!("smtp {} to {:?}", smtp_host, email);
     SmtpClientBuilder::new(smtp_host, 25)
         .allow_invalid_certs()
         .implicit_tls(false)
         .connect()
-        .await
-        .unwrap()
+        .await?
         .send(email)
         .await
-        .unwrap();
 }
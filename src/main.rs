@@ -1,40 +1,107 @@
 use rocket::{fairing::AdHoc, serde::Deserialize};
 use rocket_db_pools::Database;
 
+mod address;
+mod alerts;
+mod cache;
+mod chain;
+mod digest;
 mod email;
 mod models;
 mod qury;
+mod reorgs;
+mod rollups;
 mod route;
 mod sql;
+mod stream;
 mod time;
 mod timer;
+mod ws;
 
 #[macro_use]
 extern crate rocket;
 
-#[derive(Deserialize)]
+#[derive(Deserialize, Clone)]
 #[serde(crate = "rocket::serde")]
 pub struct AppConfig {
     smtp: String,
     site: String,
     from_name: String,
     from_email: String,
+    // accounts allowed on /admin routes
+    #[serde(default)]
+    admin_emails: Vec<String>,
+    #[serde(default)]
+    chain: chain::ChainConfig,
+    // chains served alongside the default one under /chains/<name>
+    #[serde(default)]
+    chains: Vec<chain::ChainConfig>,
+    #[serde(default)]
+    cash: models::coin::CashConfig,
+    #[serde(default)]
+    alerts: alerts::AlertConfig,
+    #[serde(default)]
+    digest: digest::DigestConfig,
+    #[serde(default)]
+    ws: ws::WsConfig,
 }
 
 #[launch]
 fn rocket() -> _ {
     rocket::build()
+        .manage(cache::TopCache::default())
         .attach(sql::AuthDb::init())
         .attach(sql::migrate())
         .attach(AdHoc::config::<AppConfig>())
+        .attach(chain::registry())
         .attach(timer::Timer::new())
+        .attach(address::Checksum)
+        .attach(rollups::maintainer())
+        .attach(reorgs::checker())
+        .attach(alerts::evaluator())
+        .attach(digest::scheduler())
         .mount(
             "/",
             routes![
                 route::auth,
                 route::register,
+                route::chain,
+                route::chains_list,
+                route::chain_by_name,
                 route::pools_top,
-                route::pools_since
+                route::pools_since,
+                route::pool_quote,
+                route::best_route,
+                route::pool_stream,
+                route::chain_pool_stream,
+                route::subscribe,
+                route::chain_subscribe,
+                route::admin_reorg,
+                route::chain_admin_reorg,
+                route::pool_detail,
+                route::pool_reserves,
+                route::coins_search,
+                route::coin_by_address,
+                route::coin_pools,
+                route::chain_pools_top,
+                route::chain_pool_quote,
+                route::chain_best_route,
+                route::chain_pools_since,
+                route::chain_pool_detail,
+                route::chain_pool_reserves,
+                route::chain_coins_search,
+                route::chain_coin_by_address,
+                route::chain_coin_pools,
+                route::alerts,
+                route::alerts_create,
+                route::alerts_update,
+                route::alerts_delete,
+                route::watchlist_pools,
+                route::watchlist_add,
+                route::watchlist_delete,
+                route::digest_subscribe,
+                route::digest_unsubscribe,
+                route::digest_unsubscribe_link
             ],
         )
 }
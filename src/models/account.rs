@@ -1,6 +1,7 @@
 use rand::RngCore;
+use rocket_db_pools::sqlx::FromRow;
 
-#[derive(Debug)]
+#[derive(Debug, FromRow)]
 pub struct Account {
     pub id: String,
     pub email: String,
@@ -0,0 +1,249 @@
+use rocket::serde::{Deserialize, Serialize};
+use rocket_db_pools::sqlx::{self, FromRow, PgConnection};
+use sqlx::types::BigDecimal;
+use std::str::FromStr;
+
+use crate::address::Address;
+use crate::sql::{query, query_as};
+
+use super::{account, price::Price};
+
+#[derive(Serialize, Debug, FromRow)]
+pub struct Alert {
+    pub id: String,
+    #[serde(skip_serializing)]
+    pub account_id: String,
+    pub pool_contract_address: String,
+    // 0 prices token0 in token1, 1 prices token1 in token0
+    pub side: i32,
+    pub above: bool,
+    #[serde(serialize_with = "super::reserve::bigdecimal_to_str")]
+    pub price: BigDecimal,
+    pub recurring: bool,
+    pub active: bool,
+    pub checked_block: Option<i32>,
+    pub notified_at: Option<i64>,
+}
+
+// an active alert with its owner's email
+#[derive(FromRow)]
+pub struct ActiveAlert {
+    #[sqlx(flatten)]
+    pub alert: Alert,
+    pub email: String,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(crate = "rocket::serde")]
+pub struct NewAlert {
+    pub pool_contract_address: String,
+    pub side: i32,
+    pub above: bool,
+    pub price: String,
+    #[serde(default)]
+    pub recurring: bool,
+}
+
+impl Alert {
+    pub fn from_new(account_id: &str, new_alert: NewAlert) -> Result<Alert, String> {
+        let price = new_alert.validate()?;
+        let pool_contract_address = new_alert.pool_contract_address.parse::<Address>()?;
+        Ok(Alert {
+            id: account::get_nice_rand_str(),
+            account_id: account_id.to_owned(),
+            pool_contract_address: pool_contract_address.to_db(),
+            side: new_alert.side,
+            above: new_alert.above,
+            price,
+            recurring: new_alert.recurring,
+            active: true,
+            checked_block: None,
+            notified_at: None,
+        })
+    }
+
+    // swap direction that prices this alert's side
+    pub fn direction(&self) -> bool {
+        self.side == 1
+    }
+
+    pub fn crossed(&self, price: &Price) -> bool {
+        if self.above {
+            price.value() >= &self.price
+        } else {
+            price.value() <= &self.price
+        }
+    }
+
+    pub fn can_notify(&self, now: i64, min_interval_secs: i64) -> bool {
+        match self.notified_at {
+            Some(notified_at) => now - notified_at >= min_interval_secs,
+            None => true,
+        }
+    }
+}
+
+impl NewAlert {
+    fn validate(&self) -> Result<BigDecimal, String> {
+        if self.side != 0 && self.side != 1 {
+            return Err(format!("bad side {}", self.side));
+        }
+        parse_price(&self.price)
+    }
+}
+
+fn parse_price(price: &str) -> Result<BigDecimal, String> {
+    match BigDecimal::from_str(price) {
+        Ok(price) if price > BigDecimal::from(0) => Ok(price),
+        _ => Err(format!("bad price {}", price)),
+    }
+}
+
+pub async fn find_by_account(db: &mut PgConnection, account_id: &str) -> Vec<Alert> {
+    match query_as("SELECT * FROM alerts WHERE account_id = $1 order by id")
+        .bind(account_id)
+        .fetch_all(db)
+        .await
+    {
+        Ok(alerts) => alerts,
+        Err(_e) => vec![],
+    }
+}
+
+pub async fn find_by_id(db: &mut PgConnection, account_id: &str, id: &str) -> Option<Alert> {
+    match query_as("SELECT * FROM alerts WHERE account_id = $1 and id = $2")
+        .bind(account_id)
+        .bind(id)
+        .fetch_one(db)
+        .await
+    {
+        Ok(alert) => Some(alert),
+        Err(_e) => None,
+    }
+}
+
+pub async fn find_active(db: &mut PgConnection) -> Vec<ActiveAlert> {
+    match query_as("SELECT alerts.*, auth.email FROM alerts JOIN auth ON auth.id = alerts.account_id WHERE alerts.active")
+        .fetch_all(db)
+        .await
+    {
+        Ok(alerts) => alerts,
+        Err(_e) => vec![],
+    }
+}
+
+pub async fn insert(db: &mut PgConnection, alert: &Alert) -> Result<(), String> {
+    query("INSERT INTO alerts (id, account_id, pool_contract_address, side, above, price, recurring, active) values ($1, $2, $3, $4, $5, $6, $7, $8)")
+        .bind(&alert.id)
+        .bind(&alert.account_id)
+        .bind(&alert.pool_contract_address)
+        .bind(alert.side)
+        .bind(alert.above)
+        .bind(&alert.price)
+        .bind(alert.recurring)
+        .bind(alert.active)
+        .execute(db)
+        .await
+        .map(|_| ())
+        .map_err(|e| e.to_string())
+}
+
+// edits re-arm the alert from the newest block
+pub async fn update(db: &mut PgConnection, alert: &Alert) -> Result<(), String> {
+    query("UPDATE alerts SET pool_contract_address = $3, side = $4, above = $5, price = $6, recurring = $7, active = $8, checked_block = NULL WHERE account_id = $1 and id = $2")
+        .bind(&alert.account_id)
+        .bind(&alert.id)
+        .bind(&alert.pool_contract_address)
+        .bind(alert.side)
+        .bind(alert.above)
+        .bind(&alert.price)
+        .bind(alert.recurring)
+        .bind(alert.active)
+        .execute(db)
+        .await
+        .map(|_| ())
+        .map_err(|e| e.to_string())
+}
+
+pub async fn delete(db: &mut PgConnection, account_id: &str, id: &str) -> bool {
+    match query("DELETE FROM alerts WHERE account_id = $1 and id = $2")
+        .bind(account_id)
+        .bind(id)
+        .execute(db)
+        .await
+    {
+        Ok(result) => result.rows_affected() > 0,
+        Err(_e) => false,
+    }
+}
+
+pub async fn mark_checked(
+    db: &mut PgConnection,
+    id: &str,
+    checked_block: u32,
+) -> Result<(), String> {
+    query("UPDATE alerts SET checked_block = $2 WHERE id = $1")
+        .bind(id)
+        .bind(checked_block as i32)
+        .execute(db)
+        .await
+        .map(|_| ())
+        .map_err(|e| e.to_string())
+}
+
+// one-shot alerts switch off once they fire
+pub async fn mark_notified(
+    db: &mut PgConnection,
+    alert: &Alert,
+    notified_at: i64,
+) -> Result<(), String> {
+    query("UPDATE alerts SET notified_at = $2, active = $3 WHERE id = $1")
+        .bind(&alert.id)
+        .bind(notified_at)
+        .bind(alert.recurring)
+        .execute(db)
+        .await
+        .map(|_| ())
+        .map_err(|e| e.to_string())
+}
+
+#[cfg(test)]
+mod test {
+    use super::Alert;
+    use crate::models::price::Price;
+    use sqlx::types::BigDecimal;
+
+    fn alert(above: bool) -> Alert {
+        Alert {
+            id: "alert".to_owned(),
+            account_id: "account".to_owned(),
+            pool_contract_address: "test-contract-usdc-weth".to_owned(),
+            side: 1,
+            above,
+            price: BigDecimal::from(4000),
+            recurring: false,
+            active: true,
+            checked_block: None,
+            notified_at: None,
+        }
+    }
+
+    #[test]
+    fn crossed() {
+        let high = Price::from(BigDecimal::from(4100));
+        let low = Price::from(BigDecimal::from(3900));
+        assert!(alert(true).crossed(&high));
+        assert!(!alert(true).crossed(&low));
+        assert!(alert(false).crossed(&low));
+        assert!(!alert(false).crossed(&high));
+    }
+
+    #[test]
+    fn rate_limit() {
+        let mut alert = alert(true);
+        assert!(alert.can_notify(1000, 3600));
+        alert.notified_at = Some(1000);
+        assert!(!alert.can_notify(2000, 3600));
+        assert!(alert.can_notify(4600, 3600));
+    }
+}
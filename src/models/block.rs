@@ -1,9 +1,10 @@
 use rocket::serde::Serialize;
-use rocket_db_pools::sqlx::{PgConnection, Postgres, Row};
+use rocket_db_pools::sqlx::{FromRow, PgConnection};
 use std::ops::Add;
 use std::time::Duration;
 
-use crate::sql::query;
+use crate::sql::query_as;
+use crate::time::Since;
 
 #[derive(Debug, Serialize, Clone)]
 pub struct Number(u32);
@@ -15,9 +16,8 @@ impl From<i32> for Number {
 }
 
 impl Number {
-    pub fn hours_ago(&self, hours: u32) -> Number {
-        let blocks_per_hour = 12 * 60;
-        Number(self.0 - (hours * blocks_per_hour))
+    pub fn previous(&self) -> Number {
+        Number(self.0.saturating_sub(1))
     }
 }
 
@@ -27,6 +27,12 @@ impl From<&Number> for i32 {
     }
 }
 
+impl From<&Number> for u32 {
+    fn from(value: &Number) -> Self {
+        value.0
+    }
+}
+
 #[derive(Debug, Serialize, Clone)]
 pub struct Timestamp(u32);
 
@@ -38,51 +44,73 @@ impl From<Timestamp> for time::OffsetDateTime {
     }
 }
 
-#[derive(Debug, Serialize)]
+impl From<i32> for Timestamp {
+    fn from(value: i32) -> Self {
+        Timestamp(value as u32)
+    }
+}
+
+impl From<&Timestamp> for i32 {
+    fn from(value: &Timestamp) -> Self {
+        value.0 as i32
+    }
+}
+
+impl From<time::OffsetDateTime> for Timestamp {
+    fn from(value: time::OffsetDateTime) -> Self {
+        Timestamp(value.unix_timestamp().clamp(0, u32::MAX as i64) as u32)
+    }
+}
+
+#[derive(Debug, Serialize, FromRow)]
 pub struct Block {
     pub hash: String,
+    // null on blocks indexed before parent linkage was recorded
+    #[sqlx(default)]
+    pub parent_hash: Option<String>,
+    #[sqlx(try_from = "i32")]
     pub number: Number,
+    #[sqlx(try_from = "i32")]
     pub timestamp: Timestamp,
 }
 
-impl Block {
-    pub fn from_row(row: &<Postgres as rocket_db_pools::sqlx::Database>::Row) -> Block {
-        Block {
-            hash: row.get::<String, &str>("hash"),
-            number: row.get::<i32, &str>("number").into(),
-            timestamp: Timestamp(row.get::<i32, &str>("timestamp") as u32),
-        }
+pub async fn find_by_number(db: &mut PgConnection, number: u32) -> Option<Block> {
+    match query_as("SELECT * FROM blocks WHERE number = $1")
+        .bind(number as i32)
+        .fetch_one(db)
+        .await
+    {
+        Ok(block) => Some(block),
+        Err(_e) => None,
     }
 }
 
-pub async fn find_by_number(db: &mut PgConnection, number: u32) -> Option<Block> {
-    match query("SELECT * FROM blocks WHERE number = $1")
-        .bind(number as i32)
+pub async fn find_by_timestamp(db: &mut PgConnection, timestamp: &Timestamp) -> Option<Block> {
+    match query_as("SELECT * FROM blocks WHERE timestamp >= $1 order by timestamp asc limit 1")
+        .bind(timestamp.0 as i32)
         .fetch_one(db)
         .await
     {
-        Ok(row) => Some(Block::from_row(&row)),
+        Ok(block) => Some(block),
         Err(_e) => None,
     }
 }
 
-// pub async fn find_by_timestamp(db: &mut PgConnection, timestamp: u32) -> Option<Block> {
-//     match query("SELECT * FROM blocks WHERE timestamp >= $1 order by timestamp asc limit 1")
-//         .bind(timestamp as i32)
-//         .fetch_one(db)
-//         .await
-//     {
-//         Ok(row) => Some(Block::from_row(&row)),
-//         Err(_e) => None,
-//     }
-// }
+// exclusive start block for a window ending at latest
+pub async fn find_since(db: &mut PgConnection, latest: &Block, since: &Since) -> Number {
+    let start = since.timestamp(latest.timestamp.clone().into());
+    match find_by_timestamp(db, &start.into()).await {
+        Some(block) => block.number.previous(),
+        None => latest.number.clone(),
+    }
+}
 
 pub async fn find_latest(db: &mut PgConnection) -> Option<Block> {
-    match query("SELECT * FROM blocks order by number desc limit 1")
+    match query_as("SELECT * FROM blocks order by number desc limit 1")
         .fetch_one(db)
         .await
     {
-        Ok(row) => Some(Block::from_row(&row)),
+        Ok(block) => Some(block),
         Err(_e) => None,
     }
 }
@@ -1,9 +1,9 @@
-use rocket::serde::Serialize;
-use rocket_db_pools::sqlx::{PgConnection, Postgres, Row};
+use rocket::serde::{Deserialize, Serialize};
+use rocket_db_pools::sqlx::{FromRow, PgConnection};
 
-use crate::sql::query;
+use crate::sql::query_as;
 
-#[derive(Serialize, Debug)]
+#[derive(Serialize, Debug, FromRow)]
 pub struct Coin {
     pub contract_address: String,
     pub name: String,
@@ -11,31 +11,118 @@ pub struct Coin {
     pub decimals: i32,
 }
 
-impl Coin {
-    pub fn from_row(row: &<Postgres as rocket_db_pools::sqlx::Database>::Row) -> Coin {
-        Coin {
-            contract_address: row.get::<String, &str>("contract_address"),
-            name: row.get::<String, &str>("name"),
-            symbol: row.get::<String, &str>("symbol"),
-            decimals: row.get::<i32, &str>("decimals"),
-        }
-    }
-}
-
 pub async fn find_by_address(db: &mut PgConnection, contract_address: &str) -> Option<Coin> {
-    match query("SELECT * FROM coins WHERE contract_address = $1")
+    match query_as("SELECT * FROM coins WHERE contract_address = $1")
         .bind(contract_address)
         .fetch_one(db)
         .await
     {
-        Ok(row) => Some(Coin::from_row(&row)),
+        Ok(coin) => Some(coin),
         Err(_e) => None,
     }
 }
 
-pub fn is_cash_token(token_address: &str) -> bool {
-    match token_address {
-        "c02aaa39b223fe8d0a0e5c4f27ead9083c756cc2" => true, // WETH
-        _ => false,
+// case-insensitive match on name or symbol, symbol matches first
+pub async fn search(db: &mut PgConnection, q: &str, limit: i64, offset: i64) -> Vec<Coin> {
+    let pattern = format!("%{}%", escape_like(q));
+    match query_as("SELECT * FROM coins WHERE name ILIKE $1 or symbol ILIKE $1 order by (symbol ILIKE $2) desc, symbol, contract_address limit $3 offset $4")
+        .bind(&pattern)
+        .bind(escape_like(q))
+        .bind(limit)
+        .bind(offset)
+        .fetch_all(db)
+        .await
+    {
+        Ok(coins) => coins,
+        Err(_e) => vec![],
+    }
+}
+
+fn escape_like(q: &str) -> String {
+    q.replace('\\', "\\\\")
+        .replace('%', "\\%")
+        .replace('_', "\\_")
+}
+
+pub const WETH: &str = "c02aaa39b223fe8d0a0e5c4f27ead9083c756cc2";
+const USDC: &str = "a0b86991c6218b36c1d19d4a2e9eb0ce3606eb48";
+const USDT: &str = "dac17f958d2ee523a2206206994597c13d831ec7";
+const DAI: &str = "6b175474e89094c44da98b954eedeac495271d0f";
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(crate = "rocket::serde", default)]
+pub struct CashConfig {
+    // quote tokens, most preferred first
+    pub cash_tokens: Vec<String>,
+    // tokens valued at one dollar
+    pub stable_tokens: Vec<String>,
+    // pools pairing a cash token with a stable token, used for usd pricing
+    pub stable_pools: Vec<String>,
+}
+
+impl Default for CashConfig {
+    fn default() -> Self {
+        CashConfig {
+            cash_tokens: vec![
+                WETH.to_owned(),
+                USDC.to_owned(),
+                USDT.to_owned(),
+                DAI.to_owned(),
+            ],
+            stable_tokens: vec![USDC.to_owned(), USDT.to_owned(), DAI.to_owned()],
+            stable_pools: vec![
+                "b4e16d0168e52d35cacd2c6185b44281ec28c9dc".to_owned(), // USDC/WETH
+                "0d4a11d5eeaac28ec3f61d100daf4d40471f1852".to_owned(), // WETH/USDT
+                "a478c2975ab1ea89e8196811f51a7b7ade33eb11".to_owned(), // DAI/WETH
+            ],
+        }
+    }
+}
+
+impl CashConfig {
+    pub fn priority(&self, token_address: &str) -> Option<usize> {
+        self.cash_tokens
+            .iter()
+            .position(|cash| cash.eq_ignore_ascii_case(token_address))
+    }
+
+    pub fn is_cash_token(&self, token_address: &str) -> bool {
+        self.priority(token_address).is_some()
+    }
+
+    pub fn is_stable_token(&self, token_address: &str) -> bool {
+        self.stable_tokens
+            .iter()
+            .any(|stable| stable.eq_ignore_ascii_case(token_address))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{escape_like, CashConfig};
+
+    #[test]
+    fn priority_order() {
+        let cash = CashConfig::default();
+        assert_eq!(
+            cash.priority("c02aaa39b223fe8d0a0e5c4f27ead9083c756cc2"),
+            Some(0)
+        );
+        assert_eq!(
+            cash.priority("A0B86991C6218B36C1D19D4A2E9EB0CE3606EB48"),
+            Some(1)
+        );
+        assert_eq!(
+            cash.priority("0000000000000000000000000000000000000000"),
+            None
+        );
+        assert!(!cash.is_stable_token("c02aaa39b223fe8d0a0e5c4f27ead9083c756cc2"));
+        assert!(cash.is_stable_token("6b175474e89094c44da98b954eedeac495271d0f"));
+    }
+
+    #[test]
+    fn like_escape() {
+        assert_eq!(escape_like("weth"), "weth");
+        assert_eq!(escape_like("100%_\\"), "100\\%\\_\\\\");
     }
 }
@@ -1,7 +1,13 @@
 pub mod account;
+pub mod alert;
 pub mod block;
 pub mod coin;
 pub mod pool;
+pub mod price;
+pub mod quote;
+pub mod reorg;
 pub mod reserve;
+pub mod rollup;
 pub mod swap;
 pub mod top_pool;
+pub mod watchlist;
@@ -1,86 +1,168 @@
 use rocket::serde::Serialize;
-use rocket_db_pools::sqlx::{self, PgConnection, Postgres, Row};
+use rocket_db_pools::sqlx::{self, FromRow, PgConnection};
 use sqlx::types::BigDecimal;
 
-use crate::sql::query;
+use crate::sql::query_as;
 
 use super::{
-    coin::{self, Coin},
+    coin::{CashConfig, Coin},
     reserve::{self, Reserve},
+    rollup::HOUR,
 };
 
-#[derive(Serialize, Debug)]
+// v2 pools keep constant-product reserves, v3 pools concentrated liquidity
+#[derive(Serialize, Debug, Default, Clone, Copy, PartialEq)]
+#[serde(crate = "rocket::serde", rename_all = "lowercase")]
+pub enum Protocol {
+    // chain databases from before v3 support have no protocol column
+    #[default]
+    V2,
+    V3,
+}
+
+impl TryFrom<String> for Protocol {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        match value.as_str() {
+            "v2" => Ok(Protocol::V2),
+            "v3" => Ok(Protocol::V3),
+            _ => Err(format!("unknown protocol {}", value)),
+        }
+    }
+}
+
+#[derive(Serialize, Debug, FromRow)]
 pub struct Pool {
     pub contract_address: String,
     pub token0: String,
     pub token1: String,
+    #[sqlx(try_from = "String", default)]
+    pub protocol: Protocol,
+    // hundredths of a basis point, v3 only
+    #[sqlx(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub fee: Option<i32>,
+    #[sqlx(skip)]
     #[serde(skip_serializing_if = "Option::is_none")]
     pub reserve: Option<Reserve>,
+    #[sqlx(skip)]
     #[serde(skip_serializing_if = "Option::is_none")]
     pub coin0: Option<Coin>,
+    #[sqlx(skip)]
     #[serde(skip_serializing_if = "Option::is_none")]
     pub coin1: Option<Coin>,
+    #[sqlx(skip)]
     #[serde(skip_serializing_if = "Option::is_none")]
     pub count0: Option<i64>,
+    #[sqlx(skip)]
     #[serde(skip_serializing_if = "Option::is_none")]
     pub count1: Option<i64>,
+    #[sqlx(skip)]
     #[serde(skip_serializing_if = "Option::is_none")]
     pub reserve_summary: Option<reserve::Summary>,
+    #[sqlx(skip)]
     #[serde(skip_serializing_if = "Option::is_none")]
     #[serde(serialize_with = "super::reserve::optbigdecimal_to_str")]
     pub sum0: Option<BigDecimal>,
+    #[sqlx(skip)]
     #[serde(skip_serializing_if = "Option::is_none")]
     #[serde(serialize_with = "super::reserve::optbigdecimal_to_str")]
     pub sum0_eth: Option<BigDecimal>,
+    #[sqlx(skip)]
     #[serde(skip_serializing_if = "Option::is_none")]
     #[serde(serialize_with = "super::reserve::optbigdecimal_to_str")]
     pub sum1: Option<BigDecimal>,
+    #[sqlx(skip)]
     #[serde(skip_serializing_if = "Option::is_none")]
     #[serde(serialize_with = "super::reserve::optbigdecimal_to_str")]
     pub sum1_eth: Option<BigDecimal>,
+    #[sqlx(skip)]
     #[serde(skip_serializing_if = "Option::is_none")]
     #[serde(serialize_with = "super::reserve::optbigdecimal_to_str")]
     pub sum_eth: Option<BigDecimal>,
+    #[sqlx(skip)]
+    pub volume_usd: Option<f64>,
+    // left out for v3 pools, their virtual reserves are not the balances the pool holds,
+    // and for pools without a usd price on either token
+    #[sqlx(skip)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reserve_usd: Option<f64>,
 }
 
 impl Pool {
-    pub fn from_row(row: &<Postgres as rocket_db_pools::sqlx::Database>::Row) -> Pool {
-        Pool {
-            contract_address: row.get::<String, &str>("contract_address"),
-            token0: row.get::<String, &str>("token0"),
-            token1: row.get::<String, &str>("token1"),
-            reserve: None,
-            coin0: None,
-            coin1: None,
-            count0: None,
-            count1: None,
-            reserve_summary: None,
-            sum0: None,
-            sum0_eth: None,
-            sum1: None,
-            sum1_eth: None,
-            sum_eth: None,
-        }
-    }
-
-    pub(crate) fn has_cash_token(&self) -> bool {
-        self.cash_token_is_0() || self.cash_token_is_1()
+    pub(crate) fn has_cash_token(&self, cash: &CashConfig) -> bool {
+        cash.is_cash_token(&self.token0) || cash.is_cash_token(&self.token1)
     }
-    pub(crate) fn cash_token_is_0(&self) -> bool {
-        coin::is_cash_token(&self.token0)
-    }
-    pub(crate) fn cash_token_is_1(&self) -> bool {
-        coin::is_cash_token(&self.token1)
+    // when both tokens are cash, the higher priority one is the quote
+    pub(crate) fn cash_token_is_1(&self, cash: &CashConfig) -> bool {
+        match (cash.priority(&self.token0), cash.priority(&self.token1)) {
+            (Some(p0), Some(p1)) => p1 < p0,
+            (None, Some(_)) => true,
+            _ => false,
+        }
     }
 }
 
 pub async fn find_by_address(db: &mut PgConnection, contract_address: &str) -> Option<Pool> {
-    match query("SELECT * FROM pools WHERE contract_address = $1")
+    match query_as("SELECT * FROM pools WHERE contract_address = $1")
         .bind(contract_address)
         .fetch_one(db)
         .await
     {
-        Ok(row) => Some(Pool::from_row(&row)),
+        Ok(pool) => Some(pool),
         Err(_e) => None,
     }
 }
+
+pub async fn find_by_token(db: &mut PgConnection, token_address: &str) -> Vec<Pool> {
+    match query_as("SELECT * FROM pools WHERE token0 = $1 or token1 = $1 order by contract_address")
+        .bind(token_address)
+        .fetch_all(db)
+        .await
+    {
+        Ok(pools) => pools,
+        Err(_e) => vec![],
+    }
+}
+
+// seconds of the newest rollups candidates are ranked on, a range scan of each pool's rollups
+const RANK_WINDOW: i32 = 7 * 24 * HOUR;
+
+// the limit pools of token with the most recent rolled up volume
+pub async fn find_busiest_by_token(
+    db: &mut PgConnection,
+    token_address: &str,
+    limit: i64,
+) -> Vec<Pool> {
+    match query_as("SELECT * FROM pools WHERE token0 = $1 or token1 = $1 order by (select sum(sum_eth) from swap_rollups_hourly where pool_contract_address = pools.contract_address and hour > (select max(hour) from swap_rollups_hourly) - $3) desc nulls last, contract_address limit $2")
+        .bind(token_address)
+        .bind(limit)
+        .bind(RANK_WINDOW)
+        .fetch_all(db)
+        .await
+    {
+        Ok(pools) => pools,
+        Err(_e) => vec![],
+    }
+}
+
+// the limit pools pairing any of tokens_a with any of tokens_b with the most recent rolled up volume
+pub async fn find_between(
+    db: &mut PgConnection,
+    tokens_a: &[String],
+    tokens_b: &[String],
+    limit: i64,
+) -> Vec<Pool> {
+    match query_as("SELECT * FROM pools WHERE (token0 = any($1) and token1 = any($2)) or (token0 = any($2) and token1 = any($1)) order by (select sum(sum_eth) from swap_rollups_hourly where pool_contract_address = pools.contract_address and hour > (select max(hour) from swap_rollups_hourly) - $4) desc nulls last, contract_address limit $3")
+        .bind(tokens_a)
+        .bind(tokens_b)
+        .bind(limit)
+        .bind(RANK_WINDOW)
+        .fetch_all(db)
+        .await
+    {
+        Ok(pools) => pools,
+        Err(_e) => vec![],
+    }
+}
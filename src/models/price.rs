@@ -0,0 +1,88 @@
+use num_traits::cast::ToPrimitive;
+use rocket::serde::Serialize;
+use rocket_db_pools::sqlx::types::BigDecimal;
+
+#[derive(Debug, Clone, PartialEq, PartialOrd)]
+pub struct Price(BigDecimal);
+
+impl Price {
+    /*
+        whole units of the numerator token per whole unit of the denominator token
+        4000 USDC (6 decimals) for 1 WETH (18 decimals)
+        from_amounts(4000000000, 6, 1000000000000000000, 18) => 4000
+    */
+    pub fn from_amounts(
+        numerator: &BigDecimal,
+        numerator_decimals: i32,
+        denominator: &BigDecimal,
+        denominator_decimals: i32,
+    ) -> Price {
+        let ratio = numerator / denominator;
+        Price(ratio * pow10(denominator_decimals - numerator_decimals))
+    }
+
+    pub fn value(&self) -> &BigDecimal {
+        &self.0
+    }
+
+    pub fn to_f64(&self) -> Option<f64> {
+        self.0.to_f64()
+    }
+}
+
+impl From<BigDecimal> for Price {
+    fn from(value: BigDecimal) -> Self {
+        Price(value)
+    }
+}
+
+impl Serialize for Price {
+    fn serialize<S>(&self, s: S) -> Result<S::Ok, S::Error>
+    where
+        S: rocket::serde::Serializer,
+    {
+        super::reserve::bigdecimal_to_str(&self.0.normalized(), s)
+    }
+}
+
+pub fn pow10(exponent: i32) -> BigDecimal {
+    BigDecimal::new(1.into(), -exponent as i64)
+}
+
+#[cfg(test)]
+mod test {
+    use super::{pow10, Price};
+    use rocket_db_pools::sqlx::types::BigDecimal;
+    use std::str::FromStr;
+
+    #[test]
+    fn applies_decimals() {
+        let usdc = BigDecimal::from(4_000_000_000u64);
+        let weth = BigDecimal::from(1_000_000_000_000_000_000u64);
+        assert_eq!(
+            Price::from_amounts(&usdc, 6, &weth, 18),
+            Price::from(BigDecimal::from(4000))
+        );
+        assert_eq!(
+            Price::from_amounts(&weth, 18, &usdc, 6),
+            Price::from(BigDecimal::from_str("0.00025").unwrap())
+        );
+    }
+
+    #[test]
+    fn keeps_precision() {
+        let numerator = BigDecimal::from_str("123456789012345678901234567890").unwrap();
+        let price = Price::from_amounts(&numerator, 18, &BigDecimal::from(1), 18);
+        assert_eq!(price.value(), &numerator);
+        assert_eq!(
+            rocket::serde::json::to_string(&price).unwrap(),
+            "\"123456789012345678901234567890\""
+        );
+    }
+
+    #[test]
+    fn powers_of_ten() {
+        assert_eq!(pow10(3), BigDecimal::from(1000));
+        assert_eq!(pow10(-2), BigDecimal::from_str("0.01").unwrap());
+    }
+}
@@ -0,0 +1,519 @@
+use num_traits::{Signed, Zero};
+use rocket::serde::Serialize;
+use rocket_db_pools::sqlx::PgConnection;
+use sqlx::types::BigDecimal;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::str::FromStr;
+
+use super::{
+    coin,
+    pool::{self, Pool},
+    price::{pow10, Price},
+    reserve::{self, Reserve},
+};
+
+// hundredths of a basis point, like v3 pool fees, 0.3% for v2 pools
+pub const V2_FEE: i32 = 3000;
+const FEE_DENOMINATOR: i64 = 1_000_000;
+// pools a route may pass through
+pub const MAX_HOPS: usize = 3;
+// busiest pools of either token, and of the pools joining their neighbors, a route may use
+const MAX_CANDIDATES: i64 = 20;
+// digits of a uint256, the most any token amount needs on either side of the point
+const MAX_DIGITS: i64 = 78;
+
+// a constant-product trade against one reserve, amounts in whole units
+#[derive(Serialize, Debug)]
+pub struct Quote {
+    pub block_number: u32,
+    pub side: u8,
+    #[serde(serialize_with = "super::reserve::bigdecimal_to_str")]
+    pub amount_in: BigDecimal,
+    #[serde(serialize_with = "super::reserve::bigdecimal_to_str")]
+    pub amount_out: BigDecimal,
+    // out token per in token
+    pub execution_price: Price,
+    pub mid_price: Price,
+    // percent the trade moves the price against itself, the fee excluded
+    #[serde(serialize_with = "super::reserve::bigdecimal_to_str")]
+    pub price_impact: BigDecimal,
+}
+
+// one pool along a route, amounts in whole units
+#[derive(Serialize, Debug)]
+pub struct Hop {
+    pub pool_contract_address: String,
+    pub token_in_address: String,
+    pub token_out_address: String,
+    #[serde(serialize_with = "super::reserve::bigdecimal_to_str")]
+    pub amount_in: BigDecimal,
+    #[serde(serialize_with = "super::reserve::bigdecimal_to_str")]
+    pub amount_out: BigDecimal,
+}
+
+#[derive(Serialize, Debug)]
+pub struct Route {
+    #[serde(serialize_with = "super::reserve::bigdecimal_to_str")]
+    pub amount_in: BigDecimal,
+    #[serde(serialize_with = "super::reserve::bigdecimal_to_str")]
+    pub amount_out: BigDecimal,
+    // to token per from token
+    pub execution_price: Price,
+    pub hops: Vec<Hop>,
+}
+
+/*
+    getAmountOut of the v2 router, in raw token units and rounded down
+    amount_out(1000000000000000000, 10000000000000000000000, 40000000000000, 3000) => 3987602436
+*/
+pub fn amount_out(
+    amount_in: &BigDecimal,
+    reserve_in: &BigDecimal,
+    reserve_out: &BigDecimal,
+    fee: i32,
+) -> BigDecimal {
+    let amount_in_with_fee = amount_in * BigDecimal::from(FEE_DENOMINATOR - fee as i64);
+    let denominator = reserve_in * BigDecimal::from(FEE_DENOMINATOR) + &amount_in_with_fee;
+    (amount_in_with_fee * reserve_out / denominator).with_scale(0)
+}
+
+// rejects amounts too long to scale to raw units cheaply, like 1e-999999999
+pub fn check_amount(amount: &BigDecimal) -> Result<(), String> {
+    let (_, scale) = amount.as_bigint_and_exponent();
+    let int_digits = amount.digits() as i64 - scale;
+    if scale > MAX_DIGITS || int_digits > MAX_DIGITS {
+        return Err(format!(
+            "amount must have at most {} digits before and after the point",
+            MAX_DIGITS
+        ));
+    }
+    Ok(())
+}
+
+// side 0 sells token0 for token1, side 1 sells token1 for token0
+pub fn quote(
+    reserve: &Reserve,
+    side: u8,
+    amount_in: &BigDecimal,
+    decimals0: i32,
+    decimals1: i32,
+    fee: i32,
+) -> Result<Quote, String> {
+    let x = BigDecimal::from_str(&reserve.x).map_err(|e| e.to_string())?;
+    let y = BigDecimal::from_str(&reserve.y).map_err(|e| e.to_string())?;
+    let (reserve_in, decimals_in, reserve_out, decimals_out) = match side {
+        0 => (x, decimals0, y, decimals1),
+        1 => (y, decimals1, x, decimals0),
+        _ => return Err(format!("side must be 0 or 1, not {}", side)),
+    };
+    if reserve_in.is_zero() || reserve_out.is_zero() {
+        return Err("pool has no liquidity".to_owned());
+    }
+    if !(0..FEE_DENOMINATOR).contains(&(fee as i64)) {
+        return Err(format!("bad fee {}", fee));
+    }
+    check_amount(amount_in).map_err(RouteError::Input)?;
+    let raw_in = (amount_in * pow10(decimals_in)).with_scale(0);
+    if !raw_in.is_positive() {
+        return Err("amount_in must be at least one unit of the token".to_owned());
+    }
+    let raw_out = amount_out(&raw_in, &reserve_in, &reserve_out, fee);
+    // the share of the pool the trade adds, which is how far it moves the price
+    let amount_in_with_fee = &raw_in * BigDecimal::from(FEE_DENOMINATOR - fee as i64);
+    let price_impact = &amount_in_with_fee * BigDecimal::from(100)
+        / (&reserve_in * BigDecimal::from(FEE_DENOMINATOR) + &amount_in_with_fee);
+    Ok(Quote {
+        block_number: reserve.block_number,
+        side,
+        amount_in: &raw_in * pow10(-decimals_in),
+        amount_out: &raw_out * pow10(-decimals_out),
+        execution_price: Price::from_amounts(&raw_out, decimals_out, &raw_in, decimals_in),
+        mid_price: Price::from_amounts(&reserve_out, decimals_out, &reserve_in, decimals_in),
+        price_impact,
+    })
+}
+
+// the token on the other side of pool, None when pool does not hold token
+fn other_token<'a>(pool: &'a Pool, token: &str) -> Option<&'a str> {
+    if pool.token0 == token {
+        Some(&pool.token1)
+    } else if pool.token1 == token {
+        Some(&pool.token0)
+    } else {
+        None
+    }
+}
+
+// paths of at most max_hops pools from one token to another, never passing a token twice,
+// shortest first
+pub fn paths<'a>(
+    from: &'a str,
+    to: &str,
+    pools: &'a [Pool],
+    max_hops: usize,
+) -> Vec<Vec<&'a Pool>> {
+    let mut by_token: HashMap<&str, Vec<&Pool>> = HashMap::new();
+    for pool in pools {
+        by_token.entry(&pool.token0).or_default().push(pool);
+        by_token.entry(&pool.token1).or_default().push(pool);
+    }
+    let mut found = vec![];
+    let mut partial: Vec<(Vec<&Pool>, &str)> = vec![(vec![], from)];
+    for _ in 0..max_hops {
+        let mut next = vec![];
+        for (path, token) in &partial {
+            for pool in by_token.get(token).into_iter().flatten() {
+                let other = match other_token(*pool, token) {
+                    Some(other) => other,
+                    None => continue,
+                };
+                let seen = other == from
+                    || path
+                        .iter()
+                        .any(|pool| pool.token0 == other || pool.token1 == other);
+                if seen {
+                    continue;
+                }
+                let mut path = path.clone();
+                path.push(*pool);
+                if other == to {
+                    found.push(path);
+                } else {
+                    next.push((path, other));
+                }
+            }
+        }
+        partial = next;
+    }
+    found
+}
+
+// raw amounts out of each pool along path, None when a pool has no reserve or liquidity
+fn amounts_along(
+    path: &[&Pool],
+    from: &str,
+    raw_in: &BigDecimal,
+    reserves: &HashMap<String, Reserve>,
+) -> Option<Vec<BigDecimal>> {
+    let mut token = from;
+    let mut amount = raw_in.clone();
+    let mut amounts = vec![];
+    for pool in path {
+        let reserve = reserves.get(&pool.contract_address)?;
+        let x = BigDecimal::from_str(&reserve.x).ok()?;
+        let y = BigDecimal::from_str(&reserve.y).ok()?;
+        let (reserve_in, reserve_out) = match pool.token0 == token {
+            true => (x, y),
+            false => (y, x),
+        };
+        if reserve_in.is_zero() || reserve_out.is_zero() {
+            return None;
+        }
+        amount = amount_out(
+            &amount,
+            &reserve_in,
+            &reserve_out,
+            pool.fee.unwrap_or(V2_FEE),
+        );
+        amounts.push(amount.clone());
+        token = other_token(pool, token)?;
+    }
+    Some(amounts)
+}
+
+// the path giving the most out, the shorter one on a tie
+pub fn best_path<'a>(
+    paths: Vec<Vec<&'a Pool>>,
+    from: &str,
+    raw_in: &BigDecimal,
+    reserves: &HashMap<String, Reserve>,
+) -> Option<(Vec<&'a Pool>, Vec<BigDecimal>)> {
+    let mut best: Option<(Vec<&Pool>, Vec<BigDecimal>)> = None;
+    for path in paths {
+        let amounts = match amounts_along(&path, from, raw_in, reserves) {
+            Some(amounts) => amounts,
+            None => continue,
+        };
+        let better = match &best {
+            Some((_, best_amounts)) => amounts.last() > best_amounts.last(),
+            None => true,
+        };
+        if better {
+            best = Some((path, amounts));
+        }
+    }
+    best
+}
+
+fn neighbors(pools: &[Pool], token: &str, except: &str) -> Vec<String> {
+    let tokens: HashSet<&str> = pools
+        .iter()
+        .filter_map(|pool| other_token(pool, token))
+        .filter(|other| *other != except)
+        .collect();
+    tokens.into_iter().map(|token| token.to_owned()).collect()
+}
+
+// a route request the input rules out, or one no stored pools can serve
+#[derive(Debug, PartialEq)]
+pub enum RouteError {
+    Input(String),
+    NotFound(String),
+}
+
+impl fmt::Display for RouteError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RouteError::Input(e) | RouteError::NotFound(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+/*
+    the best path of up to MAX_HOPS pools from one token to another
+    candidates are the busiest pools of either token and the busiest pools joining their neighbors
+*/
+pub async fn best_route(
+    db: &mut PgConnection,
+    from: &str,
+    to: &str,
+    amount_in: &BigDecimal,
+) -> Result<Route, RouteError> {
+    let mut decimals = HashMap::new();
+    for token in [from, to] {
+        match coin::find_by_address(db, token).await {
+            Some(coin) => decimals.insert(token.to_owned(), coin.decimals),
+            None => return Err(RouteError::NotFound(format!("coin not found {}", token))),
+        };
+    }
+    check_amount(amount_in).map_err(RouteError::Input)?;
+    let raw_in = (amount_in * pow10(decimals[from])).with_scale(0);
+    if !raw_in.is_positive() {
+        return Err(RouteError::Input(
+            "amount must be at least one unit of the token".to_owned(),
+        ));
+    }
+
+    let from_pools = pool::find_busiest_by_token(db, from, MAX_CANDIDATES).await;
+    let to_pools = pool::find_busiest_by_token(db, to, MAX_CANDIDATES).await;
+    let from_neighbors = neighbors(&from_pools, from, to);
+    let to_neighbors = neighbors(&to_pools, to, from);
+    let bridges = match from_neighbors.is_empty() || to_neighbors.is_empty() {
+        true => vec![],
+        false => pool::find_between(db, &from_neighbors, &to_neighbors, MAX_CANDIDATES).await,
+    };
+    let mut seen = HashSet::new();
+    let candidates: Vec<Pool> = from_pools
+        .into_iter()
+        .chain(to_pools)
+        .chain(bridges)
+        .filter(|pool| seen.insert(pool.contract_address.clone()))
+        .collect();
+    let paths = paths(from, to, &candidates, MAX_HOPS);
+
+    let addresses: Vec<String> = candidates
+        .iter()
+        .map(|pool| pool.contract_address.clone())
+        .collect();
+    let reserves = reserve::find_latest_by_addresses(db, &addresses).await;
+    let (path, amounts) = match best_path(paths, from, &raw_in, &reserves) {
+        Some(best) => best,
+        None => {
+            return Err(RouteError::NotFound(format!(
+                "no route from {} to {}",
+                from, to
+            )))
+        }
+    };
+
+    let mut hops = vec![];
+    let mut token_in = from.to_owned();
+    let mut hop_in = raw_in.clone();
+    for (pool, hop_out) in path.iter().zip(&amounts) {
+        let token_out = other_token(pool, &token_in).unwrap_or_default().to_owned();
+        for token in [&token_in, &token_out] {
+            if !decimals.contains_key(token) {
+                match coin::find_by_address(db, token).await {
+                    Some(coin) => decimals.insert(token.clone(), coin.decimals),
+                    None => return Err(RouteError::NotFound(format!("coin not found {}", token))),
+                };
+            }
+        }
+        hops.push(Hop {
+            pool_contract_address: pool.contract_address.clone(),
+            amount_in: &hop_in * pow10(-decimals[&token_in]),
+            amount_out: hop_out * pow10(-decimals[&token_out]),
+            token_in_address: token_in,
+            token_out_address: token_out.clone(),
+        });
+        token_in = token_out;
+        hop_in = hop_out.clone();
+    }
+    Ok(Route {
+        amount_in: &raw_in * pow10(-decimals[from]),
+        amount_out: &hop_in * pow10(-decimals[to]),
+        execution_price: Price::from_amounts(&hop_in, decimals[to], &raw_in, decimals[from]),
+        hops,
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::{amount_out, best_path, check_amount, paths, quote, V2_FEE};
+    use crate::models::pool::{Pool, Protocol};
+    use crate::models::price::Price;
+    use crate::models::reserve::Reserve;
+    use sqlx::types::BigDecimal;
+    use std::collections::HashMap;
+    use std::str::FromStr;
+
+    fn reserve(x: &str, y: &str) -> Reserve {
+        Reserve {
+            block_number: 1,
+            x: x.to_owned(),
+            y: y.to_owned(),
+            sqrt_price_x96: None,
+            tick: None,
+            liquidity: None,
+        }
+    }
+
+    fn d(value: &str) -> BigDecimal {
+        BigDecimal::from_str(value).unwrap()
+    }
+
+    fn pool(contract_address: &str, token0: &str, token1: &str) -> Pool {
+        Pool {
+            contract_address: contract_address.to_owned(),
+            token0: token0.to_owned(),
+            token1: token1.to_owned(),
+            protocol: Protocol::V2,
+            fee: None,
+            reserve: None,
+            coin0: None,
+            coin1: None,
+            count0: None,
+            count1: None,
+            reserve_summary: None,
+            sum0: None,
+            sum0_eth: None,
+            sum1: None,
+            sum1_eth: None,
+            sum_eth: None,
+            volume_usd: None,
+            reserve_usd: None,
+        }
+    }
+
+    #[test]
+    fn router_amounts() {
+        // 1 WETH into 40M USDC and 10000 WETH
+        assert_eq!(
+            amount_out(
+                &d("1000000000000000000"),
+                &d("10000000000000000000000"),
+                &d("40000000000000"),
+                V2_FEE
+            ),
+            d("3987602436")
+        );
+        // rounds down
+        assert_eq!(
+            amount_out(&d("100"), &d("1000"), &d("1000"), V2_FEE),
+            d("90")
+        );
+        assert_eq!(amount_out(&d("100"), &d("1000"), &d("1000"), 0), d("90"));
+        assert_eq!(amount_out(&d("100"), &d("900"), &d("1000"), 0), d("100"));
+    }
+
+    #[test]
+    fn quotes() {
+        // token0 18 decimals, token1 6, a trade adding a quarter of the pool after the fee
+        let pool = reserve("2991000000000000000000", "11964000000");
+        let sell0 = quote(&pool, 0, &d("1000"), 18, 6, V2_FEE).unwrap();
+        assert_eq!(sell0.amount_in, d("1000"));
+        assert_eq!(sell0.amount_out, d("2991"));
+        assert_eq!(sell0.execution_price, Price::from(d("2.991")));
+        assert_eq!(sell0.mid_price, Price::from(d("4")));
+        assert_eq!(sell0.price_impact, d("25"));
+
+        let usdc_weth = reserve("40000000000000", "10000000000000000000000");
+        let sell1 = quote(&usdc_weth, 1, &d("1"), 6, 18, V2_FEE).unwrap();
+        assert_eq!(sell1.amount_out, d("3987.602436"));
+        assert_eq!(sell1.mid_price, Price::from(d("4000")));
+
+        // amounts below one unit of the token are dropped
+        let dust = quote(&usdc_weth, 0, &d("1.0000001"), 6, 18, V2_FEE).unwrap();
+        assert_eq!(dust.amount_in, d("1"));
+    }
+
+    #[test]
+    fn bad_quotes() {
+        let pool = reserve("1000", "1000");
+        assert!(quote(&pool, 2, &d("1"), 0, 0, V2_FEE).is_err());
+        assert!(quote(&pool, 0, &d("0.5"), 0, 0, V2_FEE).is_err());
+        assert!(quote(&pool, 0, &d("-1"), 0, 0, V2_FEE).is_err());
+        assert!(quote(&pool, 0, &d("1"), 0, 0, 1_000_000).is_err());
+        assert!(quote(&reserve("0", "1000"), 0, &d("1"), 0, 0, V2_FEE).is_err());
+        assert!(quote(&pool, 0, &d("1e-999999999"), 0, 0, V2_FEE).is_err());
+    }
+
+    #[test]
+    fn amount_digits() {
+        assert!(check_amount(&d("1.5")).is_ok());
+        assert!(check_amount(&d("1e77")).is_ok());
+        assert!(check_amount(&d("1e-78")).is_ok());
+        assert!(check_amount(&d("1e78")).is_err());
+        assert!(check_amount(&d("1e-79")).is_err());
+        assert!(check_amount(&d("1e999999999")).is_err());
+    }
+
+    #[test]
+    fn routes() {
+        let pools = vec![
+            pool("ad", "a", "d"),
+            pool("ab", "a", "b"),
+            pool("bd", "b", "d"),
+            pool("bc", "b", "c"),
+            pool("cd", "c", "d"),
+            pool("ce", "c", "e"),
+            pool("de", "d", "e"),
+        ];
+        let names = |paths: &Vec<Vec<&Pool>>| -> Vec<Vec<String>> {
+            paths
+                .iter()
+                .map(|path| {
+                    path.iter()
+                        .map(|pool| pool.contract_address.clone())
+                        .collect()
+                })
+                .collect()
+        };
+        let found = paths("a", "d", &pools, 3);
+        assert_eq!(
+            names(&found),
+            vec![vec!["ad"], vec!["ab", "bd"], vec!["ab", "bc", "cd"]]
+        );
+        assert_eq!(paths("a", "d", &pools, 1).len(), 1);
+        assert!(paths("a", "f", &pools, 3).is_empty());
+
+        // the direct pool is shallow, the two hop route gives more and the third hop costs a fee
+        let mut reserves = HashMap::new();
+        reserves.insert("ad".to_owned(), reserve("1000", "1000"));
+        for address in ["ab", "bd", "bc", "cd"] {
+            reserves.insert(address.to_owned(), reserve("1000000", "1000000"));
+        }
+        let (path, amounts) = best_path(found, "a", &d("100"), &reserves).unwrap();
+        assert_eq!(path.len(), 2);
+        assert_eq!(amounts, vec![d("99"), d("98")]);
+
+        // pools without a reserve are skipped
+        let found = paths("a", "d", &pools, 3);
+        reserves.remove("bd");
+        reserves.remove("ad");
+        let (path, amounts) = best_path(found, "a", &d("100"), &reserves).unwrap();
+        assert_eq!(path.len(), 3);
+        assert_eq!(amounts, vec![d("99"), d("98"), d("97")]);
+    }
+}
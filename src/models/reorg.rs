@@ -0,0 +1,50 @@
+use rocket::serde::Serialize;
+use rocket_db_pools::sqlx::{FromRow, PgConnection, Row};
+
+use crate::sql::{query, query_as};
+
+// a rollback by the indexer, rows after fork_block up to old_tip were dropped
+#[derive(Serialize, Debug, FromRow)]
+pub struct Reorg {
+    pub detected_at: i64,
+    #[sqlx(try_from = "i32")]
+    pub fork_block: u32,
+    #[sqlx(try_from = "i32")]
+    pub old_tip: u32,
+    pub orphaned_swaps: i64,
+    pub orphaned_reserves: i64,
+}
+
+pub async fn find_last(db: &mut PgConnection) -> Option<Reorg> {
+    match query_as("SELECT * FROM reorgs order by id desc limit 1")
+        .fetch_one(db)
+        .await
+    {
+        Ok(reorg) => Some(reorg),
+        Err(_e) => None,
+    }
+}
+
+// the first of the newest depth blocks not linking to the stored block before it
+pub async fn find_first_unlinked(db: &mut PgConnection, depth: u32) -> Result<Option<u32>, String> {
+    match query("select first_unlinked_block($1) as number")
+        .bind(depth as i32)
+        .fetch_one(db)
+        .await
+    {
+        Ok(row) => Ok(row
+            .get::<Option<i32>, &str>("number")
+            .map(|number| number as u32)),
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+// drops everything after block_number and records the reorg, like the indexer does
+pub async fn rewind(db: &mut PgConnection, block_number: u32) -> Result<(), String> {
+    query("select rewind_blocks($1)")
+        .bind(block_number as i32)
+        .execute(db)
+        .await
+        .map(|_| ())
+        .map_err(|e| e.to_string())
+}
@@ -1,23 +1,43 @@
+use num_traits::Zero;
 use rocket::serde::Serialize;
-use rocket_db_pools::sqlx::{self, PgConnection, Postgres, Row};
+use rocket_db_pools::sqlx::{self, FromRow, PgConnection};
 use sqlx::types::BigDecimal;
+use std::collections::HashMap;
+use std::str::FromStr;
 
-use crate::sql::query;
+use crate::sql::query_as;
 
-use super::block;
+use super::{
+    block,
+    price::{pow10, Price},
+};
 
-#[derive(Serialize, Debug)]
+#[derive(Serialize, Debug, FromRow)]
 pub struct Reserve {
+    #[sqlx(try_from = "i32")]
     pub block_number: u32,
     pub x: String,
     pub y: String,
+    // v3 pools store virtual reserves in x and y, next to the pool state they come from
+    #[sqlx(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sqrt_price_x96: Option<String>,
+    #[sqlx(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tick: Option<i32>,
+    #[sqlx(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub liquidity: Option<String>,
 }
 
+// 2^192, squared sqrt prices are token1 per token0 scaled by it
+const Q192: &str = "6277101735386680763835789423207666416102355444464034512896";
+
 #[derive(Serialize, Debug)]
 pub struct Summary {
     pub start_block_number: block::Number,
     pub stop_block_number: block::Number,
-    pub dependent_variable: String,
+    pub dependent_variable: Option<String>,
     pub count: u64,
     #[serde(serialize_with = "super::reserve::optbigdecimal_to_str")]
     pub stddev: Option<BigDecimal>,
@@ -25,6 +45,47 @@ pub struct Summary {
     pub min: Option<BigDecimal>,
     #[serde(serialize_with = "super::reserve::optbigdecimal_to_str")]
     pub max: Option<BigDecimal>,
+    pub x: Stats,
+    pub y: Stats,
+    // sqrt of summed squared log returns of x/y
+    #[serde(serialize_with = "super::reserve::optbigdecimal_to_str")]
+    pub realized_volatility: Option<BigDecimal>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct Stats {
+    #[serde(serialize_with = "super::reserve::optbigdecimal_to_str")]
+    pub mean: Option<BigDecimal>,
+    #[serde(serialize_with = "super::reserve::optbigdecimal_to_str")]
+    pub median: Option<BigDecimal>,
+    #[serde(serialize_with = "super::reserve::optbigdecimal_to_str")]
+    pub p5: Option<BigDecimal>,
+    #[serde(serialize_with = "super::reserve::optbigdecimal_to_str")]
+    pub p95: Option<BigDecimal>,
+    #[serde(serialize_with = "super::reserve::optbigdecimal_to_str")]
+    pub stddev: Option<BigDecimal>,
+    #[serde(serialize_with = "super::reserve::optbigdecimal_to_str")]
+    pub min: Option<BigDecimal>,
+    #[serde(serialize_with = "super::reserve::optbigdecimal_to_str")]
+    pub max: Option<BigDecimal>,
+    #[serde(serialize_with = "super::reserve::optbigdecimal_to_str")]
+    pub first: Option<BigDecimal>,
+    #[serde(serialize_with = "super::reserve::optbigdecimal_to_str")]
+    pub last: Option<BigDecimal>,
+    #[serde(serialize_with = "super::reserve::optbigdecimal_to_str")]
+    pub change_pct: Option<BigDecimal>,
+}
+
+// one downsampled reserve reading with coin decimals applied
+#[derive(Serialize, Debug)]
+pub struct Point {
+    pub block_number: u32,
+    pub timestamp: block::Timestamp,
+    #[serde(serialize_with = "super::reserve::bigdecimal_to_str")]
+    pub x: BigDecimal,
+    #[serde(serialize_with = "super::reserve::bigdecimal_to_str")]
+    pub y: BigDecimal,
+    pub price: Option<Price>,
 }
 
 pub fn bigdecimal_to_str<S>(x: &BigDecimal, s: S) -> Result<S::Ok, S::Error>
@@ -44,52 +105,292 @@ where
     }
 }
 
+// a reserve row joined with its block's timestamp
+#[derive(FromRow)]
+struct TimedReserve {
+    #[sqlx(flatten)]
+    reserve: Reserve,
+    timestamp: i32,
+}
+
+// a reserve row with the pool it belongs to
+#[derive(FromRow)]
+struct AddressedReserve {
+    #[sqlx(flatten)]
+    reserve: Reserve,
+    contract_address: String,
+}
+
 impl Reserve {
-    pub fn from_row(row: &<Postgres as rocket_db_pools::sqlx::Database>::Row) -> Reserve {
-        Reserve {
-            block_number: row.get::<i32, &str>("block_number") as u32,
-            x: row.get::<String, &str>("x"),
-            y: row.get::<String, &str>("y"),
+    // token0 per token1 implied by the reserves or the sqrt price, None when a side is empty
+    pub fn price(&self, decimals0: i32, decimals1: i32) -> Option<Price> {
+        if let Some(sqrt_price_x96) = &self.sqrt_price_x96 {
+            let sqrt_price = BigDecimal::from_str(sqrt_price_x96).ok()?;
+            if sqrt_price.is_zero() {
+                return None;
+            }
+            let q192 = BigDecimal::from_str(Q192).ok()?;
+            return Some(Price::from_amounts(
+                &q192,
+                decimals0,
+                &(&sqrt_price * &sqrt_price),
+                decimals1,
+            ));
+        }
+        let x = BigDecimal::from_str(&self.x).ok()?;
+        let y = BigDecimal::from_str(&self.y).ok()?;
+        if x.is_zero() || y.is_zero() {
+            return None;
         }
+        Some(Price::from_amounts(&x, decimals0, &y, decimals1))
     }
 }
 
 pub async fn find_by_address(db: &mut PgConnection, contract_address: &str) -> Option<Reserve> {
-    match query(
+    match query_as(
         "SELECT * FROM reserves WHERE contract_address = $1 order by block_number desc limit 1",
     )
     .bind(contract_address)
     .fetch_one(db)
     .await
     {
-        Ok(row) => Some(Reserve::from_row(&row)),
+        Ok(reserve) => Some(reserve),
         Err(_e) => None,
     }
 }
 
+// the latest reserve of each of the pools, by contract address
+pub async fn find_latest_by_addresses(
+    db: &mut PgConnection,
+    contract_addresses: &[String],
+) -> HashMap<String, Reserve> {
+    match query_as::<_, AddressedReserve>(
+        "SELECT DISTINCT ON (contract_address) * FROM reserves WHERE contract_address = any($1) order by contract_address, block_number desc",
+    )
+    .bind(contract_addresses)
+    .fetch_all(db)
+    .await
+    {
+        Ok(rows) => rows
+            .into_iter()
+            .map(|row| (row.contract_address, row.reserve))
+            .collect(),
+        Err(_e) => HashMap::new(),
+    }
+}
+
+pub async fn find_by_address_at(
+    db: &mut PgConnection,
+    contract_address: &str,
+    block_number: u32,
+) -> Option<Reserve> {
+    match query_as(
+        "SELECT * FROM reserves WHERE contract_address = $1 and block_number <= $2 order by block_number desc limit 1",
+    )
+    .bind(contract_address)
+    .bind(block_number as i32)
+    .fetch_one(db)
+    .await
+    {
+        Ok(reserve) => Some(reserve),
+        Err(_e) => None,
+    }
+}
+
+// last reserve in each step_secs bucket between the two timestamps
+pub async fn history(
+    db: &mut PgConnection,
+    contract_address: &str,
+    from: &block::Timestamp,
+    to: &block::Timestamp,
+    step_secs: i32,
+    decimals0: i32,
+    decimals1: i32,
+) -> Vec<Point> {
+    match query_as::<_, TimedReserve>(
+        "SELECT DISTINCT ON (blocks.timestamp / $4) reserves.block_number, reserves.x, reserves.y, reserves.sqrt_price_x96, reserves.tick, reserves.liquidity, blocks.timestamp FROM reserves JOIN blocks ON blocks.number = reserves.block_number WHERE reserves.contract_address = $1 and blocks.timestamp > $2 and blocks.timestamp <= $3 order by blocks.timestamp / $4, reserves.block_number desc",
+    )
+    .bind(contract_address)
+    .bind::<i32>(from.into())
+    .bind::<i32>(to.into())
+    .bind(step_secs)
+    .fetch_all(db)
+    .await
+    {
+        Ok(rows) => rows
+            .into_iter()
+            .map(|TimedReserve { reserve, timestamp }| {
+                Point {
+                    block_number: reserve.block_number,
+                    timestamp: timestamp.into(),
+                    x: whole_units(&reserve.x, decimals0),
+                    y: whole_units(&reserve.y, decimals1),
+                    price: reserve.price(decimals0, decimals1),
+                }
+            })
+            .collect(),
+        Err(_e) => vec![],
+    }
+}
+
+fn whole_units(amount: &str, decimals: i32) -> BigDecimal {
+    BigDecimal::from_str(amount).unwrap_or_default() * pow10(-decimals)
+}
+
+// coin_cash_order picks the dependent variable, None for pools without a cash token;
+// None when the query fails
 pub async fn summarize(
     db: &mut PgConnection,
     contract_address: &str,
     start_block_number: &block::Number,
     stop_block_number: &block::Number,
-    coin_cash_order: bool,
-) -> Summary {
-    let dependent_var = if coin_cash_order { "x" } else { "y" };
-    let row = query(
-        &format!("SELECT stddev_pop({}::numeric), count(*), min({}::numeric), max({}::numeric)  FROM reserves WHERE contract_address = $1 and block_number > $2 and block_number <= $3", dependent_var, dependent_var, dependent_var),
+    coin_cash_order: Option<bool>,
+) -> Option<Summary> {
+    let row = match sqlx::query!(
+        r#"WITH r AS (SELECT block_number, x::numeric AS x, y::numeric AS y FROM reserves WHERE contract_address = $1 and block_number > $2 and block_number <= $3),
+    p AS (SELECT ln(x / y) - lag(ln(x / y)) OVER (ORDER BY block_number) AS log_return FROM r WHERE x > 0 and y > 0)
+    SELECT count(*) AS "count!",
+    avg(x) AS x_mean, percentile_disc(0.5) WITHIN GROUP (ORDER BY x) AS x_median, percentile_disc(0.05) WITHIN GROUP (ORDER BY x) AS x_p5, percentile_disc(0.95) WITHIN GROUP (ORDER BY x) AS x_p95,
+    stddev_pop(x) AS x_stddev, min(x) AS x_min, max(x) AS x_max, (array_agg(x ORDER BY block_number))[1] AS x_first, (array_agg(x ORDER BY block_number DESC))[1] AS x_last,
+    avg(y) AS y_mean, percentile_disc(0.5) WITHIN GROUP (ORDER BY y) AS y_median, percentile_disc(0.05) WITHIN GROUP (ORDER BY y) AS y_p5, percentile_disc(0.95) WITHIN GROUP (ORDER BY y) AS y_p95,
+    stddev_pop(y) AS y_stddev, min(y) AS y_min, max(y) AS y_max, (array_agg(y ORDER BY block_number))[1] AS y_first, (array_agg(y ORDER BY block_number DESC))[1] AS y_last,
+    (SELECT sqrt(sum(log_return * log_return)) FROM p) AS realized_volatility
+    FROM r"#,
+        contract_address,
+        i32::from(start_block_number),
+        i32::from(stop_block_number),
     )
-    .bind(contract_address)
-    .bind::<i32>(start_block_number.into())
-    .bind::<i32>(stop_block_number.into())
     .fetch_one(db)
-    .await.unwrap();
-    return Summary {
+    .await
+    {
+        Ok(row) => row,
+        Err(_e) => return None,
+    };
+    let x = Stats {
+        mean: row.x_mean,
+        median: row.x_median,
+        p5: row.x_p5,
+        p95: row.x_p95,
+        stddev: row.x_stddev,
+        min: row.x_min,
+        max: row.x_max,
+        first: row.x_first,
+        last: row.x_last,
+        change_pct: None,
+    }
+    .with_change();
+    let y = Stats {
+        mean: row.y_mean,
+        median: row.y_median,
+        p5: row.y_p5,
+        p95: row.y_p95,
+        stddev: row.y_stddev,
+        min: row.y_min,
+        max: row.y_max,
+        first: row.y_first,
+        last: row.y_last,
+        change_pct: None,
+    }
+    .with_change();
+    let dependent = match coin_cash_order {
+        Some(true) => Some(("x", &x)),
+        Some(false) => Some(("y", &y)),
+        None => None,
+    };
+    Some(Summary {
         start_block_number: start_block_number.clone(),
         stop_block_number: stop_block_number.clone(),
-        dependent_variable: dependent_var.to_string(),
-        stddev: row.get::<Option<sqlx::types::BigDecimal>, &str>("stddev_pop"),
-        count: row.get::<i64, &str>("count") as u64,
-        min: row.get::<Option<sqlx::types::BigDecimal>, &str>("min"),
-        max: row.get::<Option<sqlx::types::BigDecimal>, &str>("max"),
-    };
+        dependent_variable: dependent.map(|(var, _)| var.to_string()),
+        stddev: dependent.and_then(|(_, stats)| stats.stddev.clone()),
+        count: row.count as u64,
+        min: dependent.and_then(|(_, stats)| stats.min.clone()),
+        max: dependent.and_then(|(_, stats)| stats.max.clone()),
+        realized_volatility: row.realized_volatility,
+        x,
+        y,
+    })
+}
+
+impl Stats {
+    fn with_change(self) -> Stats {
+        Stats {
+            change_pct: percent_change(self.first.as_ref(), self.last.as_ref()),
+            ..self
+        }
+    }
+}
+
+fn percent_change(first: Option<&BigDecimal>, last: Option<&BigDecimal>) -> Option<BigDecimal> {
+    match (first, last) {
+        (Some(first), Some(last)) if !first.is_zero() => {
+            Some((last - first) * BigDecimal::from(100) / first)
+        }
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{percent_change, Reserve};
+    use crate::models::price::Price;
+    use sqlx::types::BigDecimal;
+
+    #[test]
+    fn change() {
+        let first = BigDecimal::from(200);
+        let last = BigDecimal::from(250);
+        assert_eq!(
+            percent_change(Some(&first), Some(&last)),
+            Some(BigDecimal::from(25))
+        );
+        assert_eq!(
+            percent_change(Some(&BigDecimal::from(0)), Some(&last)),
+            None
+        );
+        assert_eq!(percent_change(None, Some(&last)), None);
+    }
+
+    #[test]
+    fn implied_price() {
+        let reserve = Reserve {
+            block_number: 1,
+            x: "40000000000000".to_owned(),
+            y: "10000000000000000000000".to_owned(),
+            sqrt_price_x96: None,
+            tick: None,
+            liquidity: None,
+        };
+        assert_eq!(
+            reserve.price(6, 18),
+            Some(Price::from(BigDecimal::from(4000)))
+        );
+        let empty = Reserve {
+            block_number: 1,
+            x: "0".to_owned(),
+            y: "10000000000000000000000".to_owned(),
+            sqrt_price_x96: None,
+            tick: None,
+            liquidity: None,
+        };
+        assert_eq!(empty.price(6, 18), None);
+    }
+
+    #[test]
+    fn sqrt_price() {
+        // USDC/WETH at 2500, sqrt(1e18 / 2500e6) = 20000, times 2^96
+        let v3 = Reserve {
+            block_number: 1,
+            x: "50000000000000".to_owned(),
+            y: "20000000000000000000000".to_owned(),
+            sqrt_price_x96: Some("1584563250285286751870879006720000".to_owned()),
+            tick: Some(198079),
+            liquidity: Some("1000000000000000000".to_owned()),
+        };
+        assert_eq!(v3.price(6, 18), Some(Price::from(BigDecimal::from(2500))));
+        let uninitialized = Reserve {
+            sqrt_price_x96: Some("0".to_owned()),
+            ..v3
+        };
+        assert_eq!(uninitialized.price(6, 18), None);
+    }
 }
@@ -0,0 +1,252 @@
+use rocket_db_pools::sqlx::{Connection, PgConnection, Row};
+
+use super::block::{self, Number};
+use crate::sql::query;
+
+pub const HOUR: i32 = 60 * 60;
+
+// blocks rolled up per transaction while catching up
+pub const BATCH: u32 = 10_000;
+
+/*
+    price is token0 in raw token1 units, sums add up null aware
+*/
+const ROLL_UP_SQL: &str = "insert into swap_rollups_hourly (pool_contract_address, hour, sum_in0, sum_in0_eth, sum_in1, sum_in1_eth, sum_eth, count0, count1, min_price, max_price)
+    select pool_contract_address, hour, sum(in0), sum(in0_eth), sum(in1), sum(in1_eth), sum(in0_eth + in1_eth), count(NULLIF(in0,0)), count(NULLIF(in1,0)), min(price), max(price)
+    from (select swaps.*, blocks.timestamp / 3600 * 3600 as hour, abs(coalesce(in1, 0) - coalesce(out1, 0)) / NULLIF(abs(coalesce(in0, 0) - coalesce(out0, 0)), 0) as price
+        from swaps join blocks on blocks.number = swaps.block_number
+        where swaps.block_number > $1 and swaps.block_number <= $2) hourly
+    group by pool_contract_address, hour
+    on conflict (pool_contract_address, hour) do update set
+    sum_in0 = coalesce(swap_rollups_hourly.sum_in0 + excluded.sum_in0, swap_rollups_hourly.sum_in0, excluded.sum_in0),
+    sum_in0_eth = coalesce(swap_rollups_hourly.sum_in0_eth + excluded.sum_in0_eth, swap_rollups_hourly.sum_in0_eth, excluded.sum_in0_eth),
+    sum_in1 = coalesce(swap_rollups_hourly.sum_in1 + excluded.sum_in1, swap_rollups_hourly.sum_in1, excluded.sum_in1),
+    sum_in1_eth = coalesce(swap_rollups_hourly.sum_in1_eth + excluded.sum_in1_eth, swap_rollups_hourly.sum_in1_eth, excluded.sum_in1_eth),
+    sum_eth = coalesce(swap_rollups_hourly.sum_eth + excluded.sum_eth, swap_rollups_hourly.sum_eth, excluded.sum_eth),
+    count0 = swap_rollups_hourly.count0 + excluded.count0,
+    count1 = swap_rollups_hourly.count1 + excluded.count1,
+    min_price = least(swap_rollups_hourly.min_price, excluded.min_price),
+    max_price = greatest(swap_rollups_hourly.max_price, excluded.max_price)";
+
+// false until the rollup migration has run on this database
+pub async fn tables_exist(db: &mut PgConnection) -> bool {
+    match query("select to_regclass('rollup_progress') is not null as present")
+        .fetch_one(db)
+        .await
+    {
+        Ok(row) => row.get::<bool, &str>("present"),
+        Err(_e) => false,
+    }
+}
+
+// newest block already in the rollups
+pub async fn find_rolled_block(db: &mut PgConnection) -> Option<u32> {
+    match query("select block_number from rollup_progress")
+        .fetch_one(db)
+        .await
+    {
+        Ok(row) => Some(row.get::<i32, &str>("block_number") as u32),
+        Err(_e) => None,
+    }
+}
+
+// serializes roll_up across every api process sharing the database
+const ROLL_UP_LOCK: i64 = 0x726f6c6c7570;
+
+/*
+    adds the swaps of blocks after the rolled block up to to_block, together with the progress,
+    returning the new rolled block
+    the progress is read under lock inside the transaction, so a concurrent maintainer or an
+    indexer rewind can't make a range count twice; first_block is where an empty rollup starts
+*/
+pub async fn roll_up(
+    db: &mut PgConnection,
+    first_block: u32,
+    to_block: u32,
+) -> Result<u32, String> {
+    let mut tx = db.begin().await.map_err(|e| e.to_string())?;
+    query("select pg_advisory_xact_lock($1)")
+        .bind(ROLL_UP_LOCK)
+        .execute(&mut *tx)
+        .await
+        .map_err(|e| e.to_string())?;
+    let after_block = match query("select block_number from rollup_progress for update")
+        .fetch_optional(&mut *tx)
+        .await
+        .map_err(|e| e.to_string())?
+    {
+        Some(row) => row.get::<i32, &str>("block_number") as u32,
+        None => first_block,
+    };
+    if after_block >= to_block {
+        tx.commit().await.map_err(|e| e.to_string())?;
+        return Ok(after_block);
+    }
+    query(ROLL_UP_SQL)
+        .bind(after_block as i32)
+        .bind(to_block as i32)
+        .execute(&mut *tx)
+        .await
+        .map_err(|e| e.to_string())?;
+    query("insert into rollup_progress (id, block_number) values (true, $1) on conflict (id) do update set block_number = excluded.block_number")
+        .bind(to_block as i32)
+        .execute(&mut *tx)
+        .await
+        .map_err(|e| e.to_string())?;
+    tx.commit().await.map_err(|e| e.to_string())?;
+    Ok(to_block)
+}
+
+// block before the first swap, where a fresh rollup starts
+pub async fn find_first_block(db: &mut PgConnection) -> Option<u32> {
+    match query("select min(block_number) as block_number from swaps")
+        .fetch_one(db)
+        .await
+    {
+        Ok(row) => row
+            .get::<Option<i32>, &str>("block_number")
+            .map(|number| (number as u32).saturating_sub(1)),
+        Err(_e) => None,
+    }
+}
+
+// whole hours of a block window the rollups can answer, as [from, to) unix hours
+pub async fn whole_hours(
+    db: &mut PgConnection,
+    start_block: &Number,
+    stop_block: &Number,
+) -> Option<(i32, i32)> {
+    let rolled = find_rolled_block(db).await?;
+    let start = block::find_by_number(db, start_block.into()).await?;
+    let stop = block::find_by_number(db, u32::from(stop_block).min(rolled)).await?;
+    aligned_hours((&start.timestamp).into(), (&stop.timestamp).into())
+}
+
+// hours starting after the start block and ending before the stop block
+pub fn aligned_hours(start_timestamp: i32, stop_timestamp: i32) -> Option<(i32, i32)> {
+    let from = (start_timestamp / HOUR + 1) * HOUR;
+    let to = stop_timestamp / HOUR * HOUR;
+    match from < to {
+        true => Some((from, to)),
+        false => None,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{aligned_hours, roll_up, whole_hours, HOUR};
+    use crate::models::block::Number;
+    use crate::rocket;
+    use crate::sql::{self, query, AuthDb};
+    use rocket::local::asynchronous::Client;
+    use rocket_db_pools::sqlx::{types::BigDecimal, Connection, Row};
+    use rocket_db_pools::Database;
+
+    #[test]
+    fn alignment() {
+        let t = 1_726_000_000 / HOUR * HOUR;
+        assert_eq!(aligned_hours(t - 1, t + 3 * HOUR), Some((t, t + 3 * HOUR)));
+        // the start block's own second is outside the window
+        assert_eq!(
+            aligned_hours(t, t + 3 * HOUR),
+            Some((t + HOUR, t + 3 * HOUR))
+        );
+        assert_eq!(
+            aligned_hours(t + 1, t + 3 * HOUR - 1),
+            Some((t + HOUR, t + 2 * HOUR))
+        );
+        assert_eq!(aligned_hours(t + 1, t + 2 * HOUR - 1), None);
+    }
+
+    // sums through the rollups match the raw swaps table
+    #[rocket::async_test]
+    async fn matches_raw() {
+        let client = Client::tracked(rocket())
+            .await
+            .expect("valid rocket instance");
+        let db = AuthDb::fetch(client.rocket()).unwrap();
+        let mut conn = db.acquire().await.unwrap();
+        let mut tx = conn.begin().await.unwrap();
+        let pool = "00000000000000000000000000000000000000ff";
+        let first: i32 = 2_000_000_000;
+        let t = 1_726_000_000 / HOUR * HOUR;
+        // a block every 20 minutes for five hours, a swap in every block
+        for i in 0..16 {
+            query("insert into blocks (hash, number, timestamp) values ($1, $2, $3)")
+                .bind(format!("{:064x}", i))
+                .bind(first + i)
+                .bind(t + 600 + i * 20 * 60)
+                .execute(&mut *tx)
+                .await
+                .unwrap();
+            let (in0, in1) = match i % 3 {
+                0 => (1000 + i as i64, 0),
+                _ => (0, 7 * (i as i64 + 1)),
+            };
+            query("insert into swaps (pool_contract_address, block_number, transaction_index, in0_eth, in1_eth, in0, in1, out0, out1) values ($1, $2, 0, $3, $4, $3, $4, $5, $6)")
+                .bind(pool)
+                .bind(first + i)
+                .bind(BigDecimal::from(in0))
+                .bind(BigDecimal::from(in1))
+                .bind(BigDecimal::from(if in0 == 0 { 11 } else { 0 }))
+                .bind(BigDecimal::from(if in0 == 0 { 0 } else { 13 }))
+                .execute(&mut *tx)
+                .await
+                .unwrap();
+        }
+        // rolled up in two steps like the maintainer would, from an empty rollup
+        query("delete from rollup_progress")
+            .execute(&mut *tx)
+            .await
+            .unwrap();
+        assert_eq!(
+            roll_up(&mut tx, first as u32 - 1, first as u32 + 6).await,
+            Ok(first as u32 + 6)
+        );
+        // a second maintainer behind on the same range adds nothing
+        assert_eq!(
+            roll_up(&mut tx, first as u32 - 1, first as u32 + 6).await,
+            Ok(first as u32 + 6)
+        );
+        assert_eq!(
+            roll_up(&mut tx, first as u32 + 6, first as u32 + 15).await,
+            Ok(first as u32 + 15)
+        );
+
+        let start = Number::from(first + 1);
+        let stop = Number::from(first + 14);
+        let hours = whole_hours(&mut tx, &start, &stop).await;
+        assert_eq!(hours, Some((t + HOUR, t + 4 * HOUR)));
+        let raw = sql::pool_sums(&mut tx, &start, &stop, None, Some(pool))
+            .await
+            .unwrap();
+        let rolled = sql::pool_sums(&mut tx, &start, &stop, hours, Some(pool))
+            .await
+            .unwrap();
+        assert_eq!(raw.len(), 1);
+        assert_eq!(rolled.len(), 1);
+        for column in [
+            "sum_in0",
+            "sum_in0_eth",
+            "sum_in1",
+            "sum_in1_eth",
+            "sum_eth",
+        ] {
+            assert_eq!(
+                raw[0].get::<BigDecimal, &str>(column),
+                rolled[0].get::<BigDecimal, &str>(column),
+                "{}",
+                column
+            );
+        }
+        for column in ["count0", "count1"] {
+            assert_eq!(
+                raw[0].get::<i64, &str>(column),
+                rolled[0].get::<i64, &str>(column),
+                "{}",
+                column
+            );
+        }
+        tx.rollback().await.unwrap();
+    }
+}
@@ -1,15 +1,24 @@
-use num_traits::cast::ToPrimitive;
+use num_traits::{Signed, Zero};
 use rocket::serde::Serialize;
-use rocket_db_pools::sqlx::{self, PgConnection, Postgres, Row};
+use rocket_db_pools::sqlx::{self, FromRow, PgConnection, Row};
 use sqlx::types::BigDecimal;
+use std::fmt;
 
-use crate::sql::query;
+use crate::sql::{query, query_as};
 
-#[derive(Serialize, Debug)]
+use super::price::Price;
+
+#[derive(Serialize, Debug, FromRow)]
 pub struct Swap {
     pub pool_contract_address: String,
+    #[sqlx(try_from = "i32")]
     pub block_number: u32,
+    #[sqlx(try_from = "i32")]
     pub transaction_index: u32,
+    // unique per row and growing with inserts, whoever the writer
+    #[sqlx(default)]
+    #[serde(skip_serializing)]
+    pub id: i64,
     #[serde(skip_serializing_if = "Option::is_none")]
     #[serde(serialize_with = "super::reserve::optbigdecimal_to_str")]
     pub in0_eth: Option<BigDecimal>,
@@ -28,23 +37,18 @@ pub struct Swap {
     #[serde(skip_serializing_if = "Option::is_none")]
     #[serde(serialize_with = "super::reserve::optbigdecimal_to_str")]
     pub out1: Option<BigDecimal>,
+    // signed pool balance changes, positive into the pool
+    #[sqlx(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(serialize_with = "super::reserve::optbigdecimal_to_str")]
+    pub amount0: Option<BigDecimal>,
+    #[sqlx(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(serialize_with = "super::reserve::optbigdecimal_to_str")]
+    pub amount1: Option<BigDecimal>,
 }
 
 impl Swap {
-    pub fn from_row(row: &<Postgres as rocket_db_pools::sqlx::Database>::Row) -> Swap {
-        Swap {
-            pool_contract_address: row.get::<String, &str>("pool_contract_address"),
-            block_number: row.get::<i32, &str>("block_number") as u32,
-            transaction_index: row.get::<i32, &str>("transaction_index") as u32,
-            in0_eth: row.get("in0_eth"),
-            in1_eth: row.get("in1_eth"),
-            in0: row.get("in0"),
-            in1: row.get("in1"),
-            out0: row.get("out0"),
-            out1: row.get("out1"),
-        }
-    }
-
     /*
         direction: true
         Swap { pool_contract_address: "b4e16d0168e52d35cacd2c6185b44281ec28c9dc", block_number: 20739255, transaction_index: 138,
@@ -54,93 +58,308 @@ impl Swap {
         in1: Some(BigDecimal("107878427269709736")),
         out0: Some(BigDecimal("253278071")),
         out1: Some(BigDecimal("0")) }
-        numerator 0 / denomiator 0
+        net0 -253278071, net1 107878427269709736
     */
-    pub(crate) fn price(&self, direction: bool) -> f64 {
-        info!("direction: {} {:?}", direction, self);
-        let (numerator, denominator) = if direction {
-            if self.in0.is_some() {
-                (self.in0.clone().unwrap(), self.out1.clone().unwrap())
-            } else {
-                (self.out0.clone().unwrap(), self.in1.clone().unwrap())
-            }
+    pub(crate) fn price(
+        &self,
+        direction: bool,
+        decimals0: i32,
+        decimals1: i32,
+    ) -> Result<Price, PriceError> {
+        let net0 = self
+            .amount0
+            .clone()
+            .or_else(|| net_amount(&self.in0, &self.out0))
+            .ok_or(PriceError::Missing("amount0"))?;
+        let net1 = self
+            .amount1
+            .clone()
+            .or_else(|| net_amount(&self.in1, &self.out1))
+            .ok_or(PriceError::Missing("amount1"))?;
+        if net0.is_zero() {
+            return Err(PriceError::Zero("amount0"));
+        }
+        if net1.is_zero() {
+            return Err(PriceError::Zero("amount1"));
+        }
+        // a trade moves the two tokens in opposite directions
+        if net0.is_positive() == net1.is_positive() {
+            return Err(PriceError::SameDirection);
+        }
+        let (amount0, amount1) = (net0.abs(), net1.abs());
+        let price = if direction {
+            Price::from_amounts(&amount0, decimals0, &amount1, decimals1)
         } else {
-            if self.in0.is_some() {
-                (self.out1.clone().unwrap(), self.in0.clone().unwrap())
-            } else {
-                (self.in1.clone().unwrap(), self.out0.clone().unwrap())
-            }
+            Price::from_amounts(&amount1, decimals1, &amount0, decimals0)
         };
-        info!("numerator {} / denomiator {}", numerator, denominator);
-        (numerator / denominator).to_f64().unwrap()
+        info!(
+            "direction {} amount0 {} amount1 {} price {:?}",
+            direction, amount0, amount1, price
+        );
+        Ok(price)
     }
 }
 
-pub async fn swap_price_since(
+// in minus out, None when both legs are missing
+fn net_amount(
+    amount_in: &Option<BigDecimal>,
+    amount_out: &Option<BigDecimal>,
+) -> Option<BigDecimal> {
+    match (amount_in, amount_out) {
+        (None, None) => None,
+        (amount_in, amount_out) => {
+            Some(amount_in.clone().unwrap_or_default() - amount_out.clone().unwrap_or_default())
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum PriceError {
+    Missing(&'static str),
+    Zero(&'static str),
+    SameDirection,
+}
+
+impl fmt::Display for PriceError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PriceError::Missing(side) => write!(f, "swap {} missing", side),
+            PriceError::Zero(side) => write!(f, "swap {} is zero", side),
+            PriceError::SameDirection => write!(f, "swap amounts move in the same direction"),
+        }
+    }
+}
+
+// swaps after start_block up to and including stop_block, oldest first
+pub async fn find_by_pool_between(
     db: &mut PgConnection,
     pool_contract_address: &str,
-    direction: bool,
-    price: f64,
-    decimals: i32,
-) -> Result<(f64, Swap), String> {
-    let out_coin = if direction { "out1" } else { "out0" };
-    let in_coin = if direction { "in0_eth" } else { "in1_eth" };
-    let price_sql = format!("{} / ({} * power(10,$2))", in_coin, out_coin);
-    let sql = format!("select *, {} as price_eth from swaps where pool_contract_address = $1 and {} > 0 and {} < $3 order by block_number desc limit 1", price_sql, out_coin, price_sql);
-    match query(&sql)
+    start_block: u32,
+    stop_block: u32,
+) -> Vec<Swap> {
+    match query_as("select * from swaps where pool_contract_address = $1 and block_number > $2 and block_number <= $3 order by block_number, transaction_index")
         .bind(pool_contract_address)
-        .bind(decimals)
-        .bind(price)
-        .fetch_optional(db)
+        .bind(start_block as i32)
+        .bind(stop_block as i32)
+        .fetch_all(db)
         .await
     {
-        Ok(row_opt) => match row_opt {
-            Some(row) => Ok((row.get::<f64, &str>("price_eth"), Swap::from_row(&row))),
-            None => Err(format!(
-                "0 rows: {} {} {} {}",
-                sql, pool_contract_address, decimals, price
-            )),
-        },
+        Ok(swaps) => swaps,
+        Err(_e) => vec![],
+    }
+}
+
+// swaps stored after the one with after_id, oldest first
+pub async fn find_by_pool_after(
+    db: &mut PgConnection,
+    pool_contract_address: &str,
+    after_id: i64,
+    limit: i64,
+) -> Vec<Swap> {
+    match query_as(
+        "select * from swaps where pool_contract_address = $1 and id > $2 order by id limit $3",
+    )
+    .bind(pool_contract_address)
+    .bind(after_id)
+    .bind(limit)
+    .fetch_all(db)
+    .await
+    {
+        Ok(swaps) => swaps,
+        Err(_e) => vec![],
+    }
+}
+
+// id of the newest stored swap of the pool, 0 before the first
+pub async fn find_last_id(db: &mut PgConnection, pool_contract_address: &str) -> Option<i64> {
+    match query("select coalesce(max(id), 0) as id from swaps where pool_contract_address = $1")
+        .bind(pool_contract_address)
+        .fetch_one(db)
+        .await
+    {
+        Ok(row) => Some(row.get::<i64, &str>("id")),
+        Err(_e) => None,
+    }
+}
+
+// newest swap whose eth price for the out token is below price
+pub async fn swap_price_since(
+    db: &mut PgConnection,
+    pool_contract_address: &str,
+    direction: bool,
+    price: &BigDecimal,
+    out_decimals: i32,
+) -> Result<(Price, Swap), String> {
+    let row = sqlx::query!(
+        r#"select pool_contract_address, block_number, transaction_index, in0_eth, in1_eth, in0, in1, out0, out1 from swaps
+    where pool_contract_address = $1
+    and (CASE WHEN $4 THEN out1 ELSE out0 END) > 0
+    and (CASE WHEN $4 THEN in0_eth / (out1 * power(10::numeric, 18 - $2)) ELSE in1_eth / (out0 * power(10::numeric, 18 - $2)) END) < $3
+    order by block_number desc limit 1"#,
+        pool_contract_address,
+        out_decimals,
+        price,
+        direction,
+    )
+    .fetch_optional(db)
+    .await;
+    match row {
+        Ok(Some(row)) => {
+            let swap = Swap {
+                pool_contract_address: row.pool_contract_address,
+                block_number: row.block_number as u32,
+                transaction_index: row.transaction_index as u32,
+                id: 0,
+                in0_eth: row.in0_eth,
+                in1_eth: row.in1_eth,
+                in0: row.in0,
+                in1: row.in1,
+                out0: row.out0,
+                out1: row.out1,
+                amount0: None,
+                amount1: None,
+            };
+            let (in_eth, out) = if direction {
+                (swap.in0_eth.clone(), swap.out1.clone())
+            } else {
+                (swap.in1_eth.clone(), swap.out0.clone())
+            };
+            let price_eth = Price::from_amounts(
+                &in_eth.unwrap_or_default(),
+                18,
+                &out.unwrap_or_default(),
+                out_decimals,
+            );
+            Ok((price_eth, swap))
+        }
+        Ok(None) => Err(format!(
+            "0 rows: {} {} {} {}",
+            pool_contract_address, direction, out_decimals, price
+        )),
         Err(e) => Err(format!("{}", e)),
     }
 }
 
 #[cfg(test)]
 mod test {
-    use super::Swap;
+    use super::{PriceError, Swap};
+    use crate::models::price::Price;
     use sqlx::types::BigDecimal;
+    use std::str::FromStr;
+
+    fn swap(in0: Option<i64>, in1: Option<i64>, out0: Option<i64>, out1: Option<i64>) -> Swap {
+        Swap {
+            pool_contract_address: "test-contract-usdc-weth".to_owned(),
+            block_number: 1,
+            transaction_index: 0,
+            id: 0,
+            in0: in0.map(BigDecimal::from),
+            in0_eth: None,
+            in1: in1.map(BigDecimal::from),
+            in1_eth: None,
+            out0: out0.map(BigDecimal::from),
+            out1: out1.map(BigDecimal::from),
+            amount0: None,
+            amount1: None,
+        }
+    }
+
+    fn price(value: &str) -> Result<Price, PriceError> {
+        Ok(Price::from(BigDecimal::from_str(value).unwrap()))
+    }
+
+    #[test]
+    fn price_table() {
+        let cases = vec![
+            // buy
+            (
+                swap(Some(4000), None, None, Some(1)),
+                price("4000"),
+                price("0.00025"),
+            ),
+            // sell
+            (
+                swap(None, Some(1), Some(4000), None),
+                price("4000"),
+                price("0.00025"),
+            ),
+            // zero legs as stored in the swaps table
+            (
+                swap(Some(0), Some(1), Some(4000), Some(0)),
+                price("4000"),
+                price("0.00025"),
+            ),
+            // multi-leg, nets to 4000 in and 1 out
+            (
+                swap(Some(4100), Some(2), Some(100), Some(3)),
+                price("4000"),
+                price("0.00025"),
+            ),
+            (
+                swap(Some(0), Some(0), Some(0), Some(0)),
+                Err(PriceError::Zero("amount0")),
+                Err(PriceError::Zero("amount0")),
+            ),
+            (
+                swap(Some(4000), Some(0), None, Some(0)),
+                Err(PriceError::Zero("amount1")),
+                Err(PriceError::Zero("amount1")),
+            ),
+            (
+                swap(None, Some(1), None, None),
+                Err(PriceError::Missing("amount0")),
+                Err(PriceError::Missing("amount0")),
+            ),
+            (
+                swap(Some(4000), None, None, None),
+                Err(PriceError::Missing("amount1")),
+                Err(PriceError::Missing("amount1")),
+            ),
+            (
+                swap(Some(4000), Some(1), None, None),
+                Err(PriceError::SameDirection),
+                Err(PriceError::SameDirection),
+            ),
+        ];
+        for (swap, price0, price1) in cases {
+            assert_eq!(swap.price(true, 0, 0), price0, "{:?}", swap);
+            assert_eq!(swap.price(false, 0, 0), price1, "{:?}", swap);
+        }
+    }
 
     #[test]
-    fn price_from_buy() {
+    fn price_with_decimals() {
         let swap_buy = Swap {
             pool_contract_address: "test-contract-usdc-weth".to_owned(),
             block_number: 1,
             transaction_index: 0,
-            in0: Some(BigDecimal::from(4000)),
-            in0_eth: Some(BigDecimal::from(1)),
+            id: 0,
+            in0: Some(BigDecimal::from(4_000_000_000u64)),
+            in0_eth: Some(BigDecimal::from(1_000_000_000_000_000_000u64)),
             in1: None,
             in1_eth: None,
             out0: None,
-            out1: Some(BigDecimal::from(1)),
+            out1: Some(BigDecimal::from(1_000_000_000_000_000_000u64)),
+            amount0: None,
+            amount1: None,
         };
-        assert_eq!(swap_buy.price(true), 4000.0);
-        assert_eq!(swap_buy.price(false), 0.00025);
+        assert_eq!(swap_buy.price(true, 6, 18), price("4000"));
     }
 
     #[test]
-    fn price_from_sell() {
+    fn price_from_signed_amounts() {
+        // a v3 sell of 1 WETH for 4000 USDC, the in and out legs left empty
         let swap_sell = Swap {
-            pool_contract_address: "test-contract-usdc-weth".to_owned(),
-            block_number: 1,
-            transaction_index: 0,
-            in0: None,
-            in0_eth: None,
-            in1: Some(BigDecimal::from(1)),
-            in1_eth: Some(BigDecimal::from(1)),
-            out0: Some(BigDecimal::from(4000)),
-            out1: None,
+            amount0: Some(BigDecimal::from(-4_000_000_000i64)),
+            amount1: Some(BigDecimal::from(1_000_000_000_000_000_000u64)),
+            ..swap(None, None, None, None)
+        };
+        assert_eq!(swap_sell.price(true, 6, 18), price("4000"));
+        let swap_same = Swap {
+            amount0: Some(BigDecimal::from(1)),
+            amount1: Some(BigDecimal::from(1)),
+            ..swap(None, None, None, None)
         };
-        assert_eq!(swap_sell.price(true), 4000.0);
-        assert_eq!(swap_sell.price(false), 0.00025);
+        assert_eq!(swap_same.price(true, 0, 0), Err(PriceError::SameDirection));
     }
 }
@@ -0,0 +1,45 @@
+use rocket_db_pools::sqlx::{PgConnection, Row};
+
+use crate::sql::query;
+
+pub async fn find_by_account(db: &mut PgConnection, account_id: &str) -> Vec<String> {
+    match query(
+        "SELECT pool_contract_address FROM watchlist WHERE account_id = $1 order by created_at",
+    )
+    .bind(account_id)
+    .fetch_all(db)
+    .await
+    {
+        Ok(rows) => rows
+            .iter()
+            .map(|row| row.get::<String, &str>("pool_contract_address"))
+            .collect(),
+        Err(_e) => vec![],
+    }
+}
+
+pub async fn insert(
+    db: &mut PgConnection,
+    account_id: &str,
+    pool_contract_address: &str,
+) -> Result<(), String> {
+    query("INSERT INTO watchlist (account_id, pool_contract_address) values ($1, $2) ON CONFLICT DO NOTHING")
+        .bind(account_id)
+        .bind(pool_contract_address)
+        .execute(db)
+        .await
+        .map(|_| ())
+        .map_err(|e| e.to_string())
+}
+
+pub async fn delete(db: &mut PgConnection, account_id: &str, pool_contract_address: &str) -> bool {
+    match query("DELETE FROM watchlist WHERE account_id = $1 and pool_contract_address = $2")
+        .bind(account_id)
+        .bind(pool_contract_address)
+        .execute(db)
+        .await
+    {
+        Ok(result) => result.rows_affected() > 0,
+        Err(_e) => false,
+    }
+}
@@ -1,26 +1,37 @@
+use crate::chain::ChainConfig;
 use crate::models;
-use crate::sql;
-use crate::sql::query;
+use crate::models::coin::{self, CashConfig, Coin};
+use crate::models::pool::Pool;
+use crate::models::price::Price;
+use crate::models::reserve::Reserve;
+use crate::sql::query_as;
+use num_traits::cast::ToPrimitive;
 use rocket::serde::Serialize;
-use rocket_db_pools::sqlx::PgConnection;
-use rocket_db_pools::Connection;
+use rocket_db_pools::sqlx::{types::BigDecimal, PgConnection};
+use std::str::FromStr;
 use time::macros::format_description;
 
 #[derive(Serialize)]
 pub struct PoolSinceResponse {
     block_time: String,
-    price: f64,
-    cash: f64,
+    price: Price,
+    cash: Option<Price>,
+    volume_usd: Option<f64>,
+    // none for v3 pools, see Pool::reserve_usd
+    #[serde(skip_serializing_if = "Option::is_none")]
+    reserve_usd: Option<f64>,
     token0: models::coin::Coin,
     token1: models::coin::Coin,
     swap: models::swap::Swap,
 }
 
 pub async fn pool_price_since(
-    mut db: Connection<sql::AuthDb>,
+    db: &mut PgConnection,
     pool_contract_address: &str,
-    price0: Option<f64>,
-    price1: Option<f64>,
+    price0: Option<&str>,
+    price1: Option<&str>,
+    chain: &ChainConfig,
+    cash: &CashConfig,
 ) -> Result<PoolSinceResponse, String> {
     if price0.is_none() && price1.is_none() {
         return Err("bad params both empty".to_owned());
@@ -29,45 +40,55 @@ pub async fn pool_price_since(
         return Err("bad params both full".to_owned());
     }
 
-    match models::pool::find_by_address(&mut **db, pool_contract_address).await {
+    match models::pool::find_by_address(&mut *db, pool_contract_address).await {
         Some(pool) => {
-            let token0 = models::coin::find_by_address(&mut **db, &pool.token0)
+            let token0 = models::coin::find_by_address(&mut *db, &pool.token0)
                 .await
                 .unwrap();
-            let token1 = models::coin::find_by_address(&mut **db, &pool.token1)
+            let token1 = models::coin::find_by_address(&mut *db, &pool.token1)
                 .await
                 .unwrap();
 
-            let mut price: f64 = 0.0;
-            let mut decimal_difference = 0;
-            let mut direction = true;
-            if price0.is_some() && price1.is_none() {
-                decimal_difference = token0.decimals - token1.decimals;
-                price = price0.unwrap();
-                direction = true;
-            }
-            if price0.is_none() && price1.is_some() {
-                decimal_difference = token1.decimals - token0.decimals;
-                price = price1.unwrap();
-                direction = false;
-            }
+            let (price, out_decimals, direction) = match (price0, price1) {
+                (Some(price0), None) => (price0, token1.decimals, true),
+                (None, Some(price1)) => (price1, token0.decimals, false),
+                _ => unreachable!(),
+            };
+            let price = match BigDecimal::from_str(price) {
+                Ok(price) => price,
+                Err(e) => return Err(format!("bad price {}: {}", price, e)),
+            };
 
             let swap_opt = models::swap::swap_price_since(
-                &mut **db,
+                &mut *db,
                 pool_contract_address,
                 direction,
-                price,
-                decimal_difference,
+                &price,
+                out_decimals,
             )
             .await;
             match swap_opt {
                 Ok((swap_price_eth, swap)) => {
-                    let block = models::block::find_by_number(&mut **db, swap.block_number)
+                    let block = models::block::find_by_number(&mut *db, swap.block_number)
                         .await
                         .unwrap();
-                    const USDC_POOL: &str = "b4e16d0168e52d35cacd2c6185b44281ec28c9dc";
-                    let price_usd =
-                        pool_price_at(&mut **db, USDC_POOL, true, swap.block_number).await;
+                    let price_usd = native_usd_at(&mut *db, chain, cash, swap.block_number)
+                        .await
+                        .ok();
+                    let swap_eth = swap.in0_eth.clone().unwrap_or(BigDecimal::from(0))
+                        + swap.in1_eth.clone().unwrap_or(BigDecimal::from(0));
+                    let reserve_usd = match models::reserve::find_by_address_at(
+                        &mut *db,
+                        pool_contract_address,
+                        swap.block_number,
+                    )
+                    .await
+                    {
+                        Some(reserve) => {
+                            reserve_usd(&mut *db, cash, &token0, &token1, &reserve).await
+                        }
+                        None => None,
+                    };
                     let block_timestamp: time::OffsetDateTime = block.timestamp.into();
                     let block_time = block_timestamp
                         .format(format_description!(
@@ -77,7 +98,9 @@ pub async fn pool_price_since(
                     return Ok(PoolSinceResponse {
                         block_time,
                         price: swap_price_eth,
-                        cash: price_usd.unwrap_or(-1.0),
+                        cash: price_usd,
+                        volume_usd: eth_to_usd(&swap_eth, price_usd.as_ref()),
+                        reserve_usd,
                         swap,
                         token0,
                         token1,
@@ -92,23 +115,126 @@ pub async fn pool_price_since(
     }
 }
 
+// usd price of one whole token, routed through the configured stable pools
+pub async fn usd_price_at(
+    db: &mut PgConnection,
+    cash: &CashConfig,
+    token_address: &str,
+    block_number: u32,
+) -> Result<Price, String> {
+    if cash.is_stable_token(token_address) {
+        return Ok(Price::from(BigDecimal::from(1)));
+    }
+    for stable_pool in &cash.stable_pools {
+        let pool = match models::pool::find_by_address(db, stable_pool).await {
+            Some(pool) => pool,
+            None => continue,
+        };
+        let direction = match stable_direction(cash, &pool, token_address) {
+            Some(direction) => direction,
+            None => continue,
+        };
+        if let Ok(price) = pool_price_at(db, &pool, direction, block_number).await {
+            return Ok(price);
+        }
+    }
+    Err(format!(
+        "no usd price for {} at {}",
+        token_address, block_number
+    ))
+}
+
+// usd price of the wrapped native token, from the chain's reference pool when it has one
+pub async fn native_usd_at(
+    db: &mut PgConnection,
+    chain: &ChainConfig,
+    cash: &CashConfig,
+    block_number: u32,
+) -> Result<Price, String> {
+    if let Some(pool) = models::pool::find_by_address(db, &chain.stable_pool).await {
+        if let Some(direction) = stable_direction(cash, &pool, &chain.wrapped_native) {
+            if let Ok(price) = pool_price_at(db, &pool, direction, block_number).await {
+                return Ok(price);
+            }
+        }
+    }
+    usd_price_at(db, cash, &chain.wrapped_native, block_number).await
+}
+
+// direction true prices token1 in units of token0, None unless the pool pairs token with a stable;
+// addresses from config may differ in case from the stored ones
+fn stable_direction(cash: &CashConfig, pool: &Pool, token_address: &str) -> Option<bool> {
+    if pool.token1.eq_ignore_ascii_case(token_address) && cash.is_stable_token(&pool.token0) {
+        Some(true)
+    } else if pool.token0.eq_ignore_ascii_case(token_address) && cash.is_stable_token(&pool.token1)
+    {
+        Some(false)
+    } else {
+        None
+    }
+}
+
+pub fn eth_to_usd(wei: &BigDecimal, eth_usd: Option<&Price>) -> Option<f64> {
+    Some(wei.to_f64()? / 1e18 * eth_usd?.to_f64()?)
+}
+
+// pool tvl, doubling the priced side when only one token has a usd price
+// v3 virtual reserves are not what the pool holds, so they get none rather than a wrong tvl
+pub async fn reserve_usd(
+    db: &mut PgConnection,
+    cash: &CashConfig,
+    coin0: &Coin,
+    coin1: &Coin,
+    reserve: &Reserve,
+) -> Option<f64> {
+    if reserve.sqrt_price_x96.is_some() {
+        return None;
+    }
+    let x = BigDecimal::from_str(&reserve.x).ok()?.to_f64()? / 10f64.powi(coin0.decimals);
+    let y = BigDecimal::from_str(&reserve.y).ok()?.to_f64()? / 10f64.powi(coin1.decimals);
+    let usd0 = usd_price_at(db, cash, &coin0.contract_address, reserve.block_number)
+        .await
+        .ok()
+        .and_then(|price| price.to_f64())
+        .map(|price| price * x);
+    let usd1 = usd_price_at(db, cash, &coin1.contract_address, reserve.block_number)
+        .await
+        .ok()
+        .and_then(|price| price.to_f64())
+        .map(|price| price * y);
+    match (usd0, usd1) {
+        (Some(usd0), Some(usd1)) => Some(usd0 + usd1),
+        (Some(usd0), None) => Some(2.0 * usd0),
+        (None, Some(usd1)) => Some(2.0 * usd1),
+        (None, None) => None,
+    }
+}
+
+// price from the nearest swap at or before block_number
 pub async fn pool_price_at(
     db: &mut PgConnection,
-    pool_contract_address: &str,
+    pool: &Pool,
     direction: bool,
     block_number: u32,
-) -> Result<f64, String> {
-    let sql = "select * from swaps where pool_contract_address = $1 and block_number = $2 limit 1";
-    match query(&sql)
-        .bind(pool_contract_address)
+) -> Result<Price, String> {
+    let coin0 = match coin::find_by_address(db, &pool.token0).await {
+        Some(coin0) => coin0,
+        None => return Err(format!("coin not found {}", pool.token0)),
+    };
+    let coin1 = match coin::find_by_address(db, &pool.token1).await {
+        Some(coin1) => coin1,
+        None => return Err(format!("coin not found {}", pool.token1)),
+    };
+    let sql = "select * from swaps where pool_contract_address = $1 and block_number <= $2 order by block_number desc, transaction_index desc limit 1";
+    match query_as::<_, models::swap::Swap>(sql)
+        .bind(&pool.contract_address)
         .bind(block_number as i32)
         .fetch_one(db)
         .await
     {
-        Ok(row) => {
-            let swap = models::swap::Swap::from_row(&row);
-            Ok(swap.price(direction))
-        }
+        Ok(swap) => swap
+            .price(direction, coin0.decimals, coin1.decimals)
+            .map_err(|e| e.to_string()),
 
         Err(e) => Err(e.to_string()),
     }
@@ -116,8 +242,20 @@ pub async fn pool_price_at(
 
 #[cfg(test)]
 mod test {
+    use super::eth_to_usd;
+    use crate::models::price::Price;
+    use rocket_db_pools::sqlx::types::BigDecimal;
+
     #[test]
     fn datetime() {
         assert_eq!(1, 1)
     }
+
+    #[test]
+    fn usd_from_wei() {
+        let wei = BigDecimal::from(2_000_000_000_000_000_000u64);
+        let eth_usd = Price::from(BigDecimal::from(2500));
+        assert_eq!(eth_to_usd(&wei, Some(&eth_usd)), Some(5000.0));
+        assert_eq!(eth_to_usd(&wei, None), None);
+    }
 }
@@ -0,0 +1,54 @@
+use rocket::fairing::AdHoc;
+use rocket_db_pools::sqlx::PgConnection;
+use std::time::Duration;
+
+use crate::chain::Chains;
+use crate::models::reorg;
+
+// newest blocks whose parent linkage is checked on every run
+pub const CHECK_DEPTH: u32 = 1000;
+
+// checks each chain's stored blocks link up, whichever process wrote them
+pub fn checker() -> AdHoc {
+    AdHoc::on_liftoff("Reorg checker", |rocket| {
+        Box::pin(async move {
+            let chains = match rocket.state::<Chains>() {
+                Some(chains) => chains,
+                None => return,
+            };
+            for chain in chains.iter() {
+                let name = chain.config.name.clone();
+                let db = chain.pool().clone();
+                let poll = Duration::from_secs(chain.config.block_time_secs.max(1));
+                rocket::tokio::spawn(async move {
+                    let mut interval = rocket::tokio::time::interval(poll);
+                    loop {
+                        interval.tick().await;
+                        match db.acquire().await {
+                            Ok(mut conn) => match check(&mut conn).await {
+                                Ok(Some(fork)) => warn!("reorg {}: rewound to {}", name, fork),
+                                Ok(None) => (),
+                                Err(e) => error!("reorg {}: {}", name, e),
+                            },
+                            Err(e) => error!("reorg {}: {}", name, e),
+                        }
+                    }
+                });
+            }
+        })
+    })
+}
+
+/*
+    rewinds below the parent of the first block not linking to the one before it, either may
+    be the orphan; the block the rewind kept, if any
+*/
+pub async fn check(db: &mut PgConnection) -> Result<Option<u32>, String> {
+    let number = match reorg::find_first_unlinked(db, CHECK_DEPTH).await? {
+        Some(number) => number,
+        None => return Ok(None),
+    };
+    let fork = number.saturating_sub(2);
+    reorg::rewind(db, fork).await?;
+    Ok(Some(fork))
+}
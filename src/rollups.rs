@@ -0,0 +1,62 @@
+use rocket::fairing::AdHoc;
+use rocket_db_pools::sqlx::PgConnection;
+use std::time::Duration;
+
+use crate::chain::Chains;
+use crate::models::{block, rollup};
+
+// keeps each chain's hourly swap rollups up to its newest block
+pub fn maintainer() -> AdHoc {
+    AdHoc::on_liftoff("Rollup maintainer", |rocket| {
+        Box::pin(async move {
+            let chains = match rocket.state::<Chains>() {
+                Some(chains) => chains,
+                None => return,
+            };
+            for chain in chains.iter() {
+                let name = chain.config.name.clone();
+                let db = chain.pool().clone();
+                let poll = Duration::from_secs(chain.config.block_time_secs.max(1));
+                rocket::tokio::spawn(async move {
+                    let mut interval = rocket::tokio::time::interval(poll);
+                    loop {
+                        interval.tick().await;
+                        match db.acquire().await {
+                            Ok(mut conn) => {
+                                if let Err(e) = catch_up(&mut conn).await {
+                                    error!("rollups {}: {}", name, e)
+                                }
+                            }
+                            Err(e) => error!("rollups {}: {}", name, e),
+                        }
+                    }
+                });
+            }
+        })
+    })
+}
+
+// rolls up every block since the last run, BATCH blocks per transaction;
+// databases without the rollup tables are left alone
+pub async fn catch_up(db: &mut PgConnection) -> Result<(), String> {
+    if !rollup::tables_exist(db).await {
+        return Ok(());
+    }
+    let latest: u32 = match block::find_latest(db).await {
+        Some(latest) => (&latest.number).into(),
+        None => return Ok(()),
+    };
+    let mut rolled = match rollup::find_rolled_block(db).await {
+        Some(rolled) => rolled,
+        None => match rollup::find_first_block(db).await {
+            Some(first) => first,
+            None => return Ok(()),
+        },
+    };
+    // rolled is only a hint here, roll_up rereads it under lock
+    while rolled < latest {
+        let to_block = latest.min(rolled.saturating_add(rollup::BATCH));
+        rolled = rollup::roll_up(db, rolled, to_block).await?;
+    }
+    Ok(())
+}
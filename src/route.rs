@@ -1,11 +1,27 @@
-use crate::models::{block, pool};
-use crate::{email, qury, sql, AppConfig};
+use crate::address::Address;
+use crate::cache::{Cached, IfNoneMatch, TopCache, TopKey};
+use crate::chain::{Chain, ChainConfig, ChainDb, Chains, Tagged};
+use crate::models::account::Account;
+use crate::models::{alert, block, coin, pool, quote, reorg, reserve, swap, watchlist};
+use crate::stream::{self, LastEventId, SwapEvent};
+use crate::time::Since;
+use crate::ws::Subscriptions;
+use crate::{digest, email, qury, sql, AppConfig};
+use num_traits::Signed;
+use rocket::futures::{SinkExt, StreamExt};
 use rocket::http::{Cookie, CookieJar, Header, Status};
+use rocket::outcome::{try_outcome, Outcome};
+use rocket::request::{self, FromRequest};
 use rocket::response::status;
+use rocket::response::stream::{Event, EventStream};
 use rocket::response::Responder;
-use rocket::serde::json::Json;
-use rocket::{Request, State};
-use rocket_db_pools::Connection;
+use rocket::serde::json::{self, Json, Value};
+use rocket::tokio::select;
+use rocket::{Request, Shutdown, State};
+use rocket_db_pools::{sqlx::PgConnection, Connection};
+use sqlx::types::BigDecimal;
+use std::str::FromStr;
+use std::time::Duration;
 
 #[derive(Debug, Clone, PartialEq)]
 pub struct Cors<R>(pub R);
@@ -19,41 +35,888 @@ impl<'r, 'o: 'r, R: Responder<'r, 'o>> Responder<'r, 'o> for Cors<R> {
     }
 }
 
+// the login cookie set by /auth/<token>
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for Account {
+    type Error = &'static str;
+
+    async fn from_request(req: &'r Request<'_>) -> request::Outcome<Self, Self::Error> {
+        let token = match req.cookies().get("token") {
+            Some(cookie) => cookie.value().to_owned(),
+            None => return Outcome::Error((Status::Unauthorized, "no token")),
+        };
+        let db = match req.guard::<Connection<sql::AuthDb>>().await {
+            Outcome::Success(db) => db,
+            _ => return Outcome::Error((Status::ServiceUnavailable, "no database")),
+        };
+        match sql::find_by_token(db, &token).await {
+            Some(account) => Outcome::Success(account),
+            None => Outcome::Error((Status::Unauthorized, "bad token")),
+        }
+    }
+}
+
+// a logged in account listed in admin_emails
+pub struct Admin(pub Account);
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for Admin {
+    type Error = &'static str;
+
+    async fn from_request(req: &'r Request<'_>) -> request::Outcome<Self, Self::Error> {
+        let account = try_outcome!(req.guard::<Account>().await);
+        let admin_emails = match req.rocket().state::<AppConfig>() {
+            Some(app_config) => &app_config.admin_emails,
+            None => return Outcome::Error((Status::InternalServerError, "no config")),
+        };
+        match admin_emails.contains(&account.email) {
+            true => Outcome::Success(Admin(account)),
+            false => Outcome::Error((Status::Forbidden, "not an admin")),
+        }
+    }
+}
+
+// exclusive start and inclusive stop blocks for a since window
+async fn window(
+    db: &mut PgConnection,
+    since: Option<&str>,
+) -> Result<(block::Number, block::Number), status::Custom<Json<String>>> {
+    window_to_latest(db, since)
+        .await
+        .map(|(start_block, latest_block)| (start_block, latest_block.number))
+}
+
+// like window, with the whole stop block
+async fn window_to_latest(
+    db: &mut PgConnection,
+    since: Option<&str>,
+) -> Result<(block::Number, block::Block), status::Custom<Json<String>>> {
+    let since = match since {
+        Some(since) => {
+            Since::parse(since).map_err(|e| status::Custom(Status::BadRequest, Json(e)))?
+        }
+        None => Since::default(),
+    };
+    let latest_block = match block::find_latest(db).await {
+        Some(block) => block,
+        None => {
+            return Err(status::Custom(
+                Status::ServiceUnavailable,
+                Json("no blocks indexed".to_owned()),
+            ))
+        }
+    };
+    let start_block = block::find_since(db, &latest_block, &since).await;
+    Ok((start_block, latest_block))
+}
+
+// malformed addresses are a bad request rather than a missed route
+fn address(param: Result<Address, String>) -> Result<String, status::Custom<Json<String>>> {
+    param
+        .map(|address| address.to_db())
+        .map_err(|e| status::Custom(Status::BadRequest, Json(e)))
+}
+
+fn tag<T>(chain: &Chain, Cors(Json(data)): Cors<Json<T>>) -> Cors<Json<Tagged<T>>> {
+    Cors(Json(Tagged {
+        chain_id: chain.config.chain_id,
+        data,
+    }))
+}
+
+#[get("/chain")]
+pub(crate) async fn chain(chains: &State<Chains>) -> Cors<Json<ChainConfig>> {
+    Cors(Json(chains.default_chain().config.clone()))
+}
+
+#[get("/chains")]
+pub(crate) async fn chains_list(chains: &State<Chains>) -> Cors<Json<Vec<ChainConfig>>> {
+    Cors(Json(chains.configs().into_iter().cloned().collect()))
+}
+
+#[get("/chains/<name>")]
+pub(crate) async fn chain_by_name(
+    chains: &State<Chains>,
+    name: &str,
+) -> Result<Cors<Json<ChainConfig>>, status::Custom<Json<String>>> {
+    match chains.get(name) {
+        Some(chain) => Ok(Cors(Json(chain.config.clone()))),
+        None => Err(status::Custom(
+            Status::NotFound,
+            Json(format!("chain not found {}", name)),
+        )),
+    }
+}
+
+// results only move once per block, so they are cached by window blocks
 #[get("/pools/top?<since>")]
 pub(crate) async fn pools_top(
-    mut db: Connection<sql::AuthDb>,
+    mut db: ChainDb<'_>,
+    cache: &State<TopCache>,
+    if_none_match: IfNoneMatch,
     since: Option<&str>,
-) -> Cors<Json<Vec<pool::Pool>>> {
-    let latest_block = block::find_latest(&mut db).await.unwrap();
-    let hours_ago = match since {
-        Some(since) => since_parse(since),
-        None => 24,
+) -> Result<Cors<Cached<Json<Value>>>, status::Custom<Json<String>>> {
+    let chain = db.chain;
+    let (start_block, latest_block) = window_to_latest(&mut db, since).await?;
+    let stop_block = latest_block.number;
+    // the hash tells a block apart from the one it replaced in a reorg
+    let key = TopKey {
+        chain: chain.config.name.clone(),
+        start_block: (&start_block).into(),
+        stop_block: (&stop_block).into(),
+        stop_hash: latest_block.hash,
     };
-    Cors(Json(
-        sql::top_pools(
-            db,
-            &latest_block.number.hours_ago(hours_ago),
-            &latest_block.number,
+    let etag = key.etag();
+    let max_age = chain.config.block_time_secs;
+    if if_none_match.matches(&etag) {
+        return Ok(Cors(Cached {
+            etag,
+            max_age,
+            body: None,
+        }));
+    }
+    let pools = match cache.get(&key) {
+        Some(pools) => pools,
+        None => {
+            let pools = sql::top_pools(
+                &mut db,
+                &start_block,
+                &stop_block,
+                &chain.config,
+                &chain.cash,
+            )
+            .await;
+            let pools = json::to_value(pools)
+                .map_err(|e| status::Custom(Status::InternalServerError, Json(e.to_string())))?;
+            cache.insert(key, pools.clone());
+            pools
+        }
+    };
+    Ok(Cors(Cached {
+        etag,
+        max_age,
+        body: Some(Json(pools)),
+    }))
+}
+
+// ranked ahead of /chains/<_>/pools/<pool_id>, which would otherwise collide
+#[get("/chains/<_>/pools/top?<since>", rank = -7)]
+pub(crate) async fn chain_pools_top(
+    db: ChainDb<'_>,
+    cache: &State<TopCache>,
+    if_none_match: IfNoneMatch,
+    since: Option<&str>,
+) -> Result<Cors<Cached<Json<Tagged<Value>>>>, status::Custom<Json<String>>> {
+    let chain_id = db.chain.config.chain_id;
+    let Cors(cached) = pools_top(db, cache, if_none_match, since).await?;
+    Ok(Cors(Cached {
+        etag: cached.etag,
+        max_age: cached.max_age,
+        body: cached
+            .body
+            .map(|Json(data)| Json(Tagged { chain_id, data })),
+    }))
+}
+
+#[get("/pools/<pool_id>?<since>")]
+pub(crate) async fn pool_detail(
+    mut db: ChainDb<'_>,
+    pool_id: Result<Address, String>,
+    since: Option<&str>,
+) -> Result<Cors<Json<pool::Pool>>, status::Custom<Json<String>>> {
+    let pool_id = address(pool_id)?;
+    let chain = db.chain;
+    let (start_block, stop_block) = window(&mut db, since).await?;
+    let pool_contract_addresses = [pool_id.clone()];
+    match sql::pools_with_sums(
+        &mut db,
+        &pool_contract_addresses,
+        &start_block,
+        &stop_block,
+        &chain.config,
+        &chain.cash,
+    )
+    .await
+    .pop()
+    {
+        Some(pool) => Ok(Cors(Json(pool))),
+        None => Err(status::Custom(
+            Status::NotFound,
+            Json(format!("pool not found {}", pool_id)),
+        )),
+    }
+}
+
+#[get("/chains/<_>/pools/<pool_id>?<since>")]
+pub(crate) async fn chain_pool_detail(
+    db: ChainDb<'_>,
+    pool_id: Result<Address, String>,
+    since: Option<&str>,
+) -> Result<Cors<Json<Tagged<pool::Pool>>>, status::Custom<Json<String>>> {
+    let chain = db.chain;
+    pool_detail(db, pool_id, since).await.map(|r| tag(chain, r))
+}
+
+// from and to take the same values as since, step is a duration
+#[get("/pools/<pool_id>/reserves?<from>&<to>&<step>")]
+pub(crate) async fn pool_reserves(
+    mut db: ChainDb<'_>,
+    pool_id: Result<Address, String>,
+    from: Option<&str>,
+    to: Option<&str>,
+    step: Option<&str>,
+) -> Result<Cors<Json<Vec<reserve::Point>>>, status::Custom<Json<String>>> {
+    let pool_id = address(pool_id)?;
+    let bad_request = |e: String| status::Custom(Status::BadRequest, Json(e));
+    let from = match from {
+        Some(from) => Since::parse(from).map_err(bad_request)?,
+        None => Since::default(),
+    };
+    let to = match to {
+        Some(to) => Some(Since::parse(to).map_err(bad_request)?),
+        None => None,
+    };
+    let step = match step.map(Since::parse) {
+        Some(Ok(Since::Ago(step))) if step.whole_seconds() > 0 => step.whole_seconds(),
+        Some(Ok(_)) => return Err(bad_request("step must be a duration".to_owned())),
+        Some(Err(e)) => return Err(bad_request(e)),
+        None => 60 * 60,
+    };
+    let pool = match pool::find_by_address(&mut db, &pool_id).await {
+        Some(pool) => pool,
+        None => {
+            return Err(status::Custom(
+                Status::NotFound,
+                Json(format!("pool not found {}", pool_id)),
+            ))
+        }
+    };
+    let (coin0, coin1) = match (
+        coin::find_by_address(&mut db, &pool.token0).await,
+        coin::find_by_address(&mut db, &pool.token1).await,
+    ) {
+        (Some(coin0), Some(coin1)) => (coin0, coin1),
+        _ => {
+            return Err(status::Custom(
+                Status::NotFound,
+                Json(format!("coins not found for pool {}", pool_id)),
+            ))
+        }
+    };
+    let latest_block = match block::find_latest(&mut db).await {
+        Some(block) => block,
+        None => {
+            return Err(status::Custom(
+                Status::ServiceUnavailable,
+                Json("no blocks indexed".to_owned()),
+            ))
+        }
+    };
+    let now: time::OffsetDateTime = latest_block.timestamp.into();
+    let from_timestamp = from.timestamp(now);
+    let to_timestamp = to.map(|to| to.timestamp(now)).unwrap_or(now);
+    // keep responses to a chartable number of points, no finer than a block
+    let span = (to_timestamp - from_timestamp).whole_seconds().max(0);
+    let step = step
+        .max(span / MAX_POINTS + 1)
+        .max(db.chain.config.block_time_secs as i64)
+        .min(i32::MAX as i64) as i32;
+    Ok(Cors(Json(
+        reserve::history(
+            &mut db,
+            &pool.contract_address,
+            &from_timestamp.into(),
+            &to_timestamp.into(),
+            step,
+            coin0.decimals,
+            coin1.decimals,
         )
         .await,
+    )))
+}
+
+const MAX_POINTS: i64 = 1000;
+
+#[get("/chains/<_>/pools/<pool_id>/reserves?<from>&<to>&<step>")]
+pub(crate) async fn chain_pool_reserves(
+    db: ChainDb<'_>,
+    pool_id: Result<Address, String>,
+    from: Option<&str>,
+    to: Option<&str>,
+    step: Option<&str>,
+) -> Result<Cors<Json<Tagged<Vec<reserve::Point>>>>, status::Custom<Json<String>>> {
+    let chain = db.chain;
+    pool_reserves(db, pool_id, from, to, step)
+        .await
+        .map(|r| tag(chain, r))
+}
+
+#[get("/coins?<q>&<page>&<per_page>")]
+pub(crate) async fn coins_search(
+    mut db: ChainDb<'_>,
+    q: Option<&str>,
+    page: Option<u32>,
+    per_page: Option<u32>,
+) -> Cors<Json<Vec<coin::Coin>>> {
+    let per_page = per_page.unwrap_or(20).clamp(1, 100) as i64;
+    let offset = (page.unwrap_or(1).max(1) as i64 - 1) * per_page;
+    Cors(Json(
+        coin::search(&mut db, q.unwrap_or(""), per_page, offset).await,
     ))
 }
 
-fn since_parse(since: &str) -> u32 {
-    u32::from_str_radix(since, 10).unwrap()
+#[get("/chains/<_>/coins?<q>&<page>&<per_page>")]
+pub(crate) async fn chain_coins_search(
+    db: ChainDb<'_>,
+    q: Option<&str>,
+    page: Option<u32>,
+    per_page: Option<u32>,
+) -> Cors<Json<Tagged<Vec<coin::Coin>>>> {
+    let chain = db.chain;
+    tag(chain, coins_search(db, q, page, per_page).await)
+}
+
+#[get("/coins/<address>")]
+pub(crate) async fn coin_by_address(
+    mut db: ChainDb<'_>,
+    address: Result<Address, String>,
+) -> Result<Cors<Json<coin::Coin>>, status::Custom<Json<String>>> {
+    let address = self::address(address)?;
+    match coin::find_by_address(&mut db, &address).await {
+        Some(coin) => Ok(Cors(Json(coin))),
+        None => Err(status::Custom(
+            Status::NotFound,
+            Json(format!("coin not found {}", address)),
+        )),
+    }
+}
+
+#[get("/chains/<_>/coins/<address>")]
+pub(crate) async fn chain_coin_by_address(
+    db: ChainDb<'_>,
+    address: Result<Address, String>,
+) -> Result<Cors<Json<Tagged<coin::Coin>>>, status::Custom<Json<String>>> {
+    let chain = db.chain;
+    coin_by_address(db, address).await.map(|r| tag(chain, r))
+}
+
+#[get("/coins/<address>/pools")]
+pub(crate) async fn coin_pools(
+    mut db: ChainDb<'_>,
+    address: Result<Address, String>,
+) -> Result<Cors<Json<Vec<pool::Pool>>>, status::Custom<Json<String>>> {
+    let address = self::address(address)?;
+    Ok(Cors(Json(pool::find_by_token(&mut db, &address).await)))
+}
+
+#[get("/chains/<_>/coins/<address>/pools")]
+pub(crate) async fn chain_coin_pools(
+    db: ChainDb<'_>,
+    address: Result<Address, String>,
+) -> Result<Cors<Json<Tagged<Vec<pool::Pool>>>>, status::Custom<Json<String>>> {
+    let chain = db.chain;
+    coin_pools(db, address).await.map(|r| tag(chain, r))
 }
 
 #[get("/pools/<pool_id>/since?<price0>&<price1>")]
 pub(crate) async fn pools_since(
-    db: Connection<sql::AuthDb>,
-    pool_id: &str,
-    price0: Option<f64>,
-    price1: Option<f64>,
-) -> Result<Cors<Json<qury::PoolSinceResponse>>, Json<String>> {
-    match qury::pool_price_since(db, pool_id, price0, price1).await {
+    mut db: ChainDb<'_>,
+    pool_id: Result<Address, String>,
+    price0: Option<&str>,
+    price1: Option<&str>,
+) -> Result<Cors<Json<qury::PoolSinceResponse>>, status::Custom<Json<String>>> {
+    let pool_id = address(pool_id)?;
+    let chain = db.chain;
+    match qury::pool_price_since(
+        &mut db,
+        &pool_id,
+        price0,
+        price1,
+        &chain.config,
+        &chain.cash,
+    )
+    .await
+    {
         Ok(zo) => Ok(Cors(Json(zo))),
-        Err(e) => Err(Json(e)),
+        Err(e) => Err(status::Custom(Status::BadRequest, Json(e))),
+    }
+}
+
+#[get("/chains/<_>/pools/<pool_id>/since?<price0>&<price1>")]
+pub(crate) async fn chain_pools_since(
+    db: ChainDb<'_>,
+    pool_id: Result<Address, String>,
+    price0: Option<&str>,
+    price1: Option<&str>,
+) -> Result<Cors<Json<Tagged<qury::PoolSinceResponse>>>, status::Custom<Json<String>>> {
+    let chain = db.chain;
+    pools_since(db, pool_id, price0, price1)
+        .await
+        .map(|r| tag(chain, r))
+}
+
+// a trade against the latest reserve, side 0 sells token0 and side 1 sells token1
+#[get("/pools/<pool_id>/quote?<amount_in>&<side>")]
+pub(crate) async fn pool_quote(
+    mut db: ChainDb<'_>,
+    pool_id: Result<Address, String>,
+    amount_in: Option<&str>,
+    side: Option<&str>,
+) -> Result<Cors<Json<quote::Quote>>, status::Custom<Json<String>>> {
+    let pool_id = address(pool_id)?;
+    let bad_request = |e: String| status::Custom(Status::BadRequest, Json(e));
+    let amount_in = match amount_in.map(BigDecimal::from_str) {
+        Some(Ok(amount_in)) => amount_in,
+        Some(Err(e)) => return Err(bad_request(format!("bad amount_in: {}", e))),
+        None => return Err(bad_request("amount_in required".to_owned())),
+    };
+    quote::check_amount(&amount_in).map_err(bad_request)?;
+    let side = match side {
+        Some("0") => 0,
+        Some("1") => 1,
+        _ => return Err(bad_request("side must be 0 or 1".to_owned())),
+    };
+    let pool = match pool::find_by_address(&mut db, &pool_id).await {
+        Some(pool) => pool,
+        None => {
+            return Err(status::Custom(
+                Status::NotFound,
+                Json(format!("pool not found {}", pool_id)),
+            ))
+        }
+    };
+    // the constant-product formula only holds for v3 within the current tick range
+    if pool.protocol == pool::Protocol::V3 {
+        return Err(bad_request(format!("no quotes for v3 pool {}", pool_id)));
+    }
+    let (coin0, coin1) = match (
+        coin::find_by_address(&mut db, &pool.token0).await,
+        coin::find_by_address(&mut db, &pool.token1).await,
+    ) {
+        (Some(coin0), Some(coin1)) => (coin0, coin1),
+        _ => {
+            return Err(status::Custom(
+                Status::NotFound,
+                Json(format!("coins not found for pool {}", pool_id)),
+            ))
+        }
+    };
+    let reserve = match reserve::find_by_address(&mut db, &pool_id).await {
+        Some(reserve) => reserve,
+        None => {
+            return Err(status::Custom(
+                Status::NotFound,
+                Json(format!("no reserves for pool {}", pool_id)),
+            ))
+        }
+    };
+    quote::quote(
+        &reserve,
+        side,
+        &amount_in,
+        coin0.decimals,
+        coin1.decimals,
+        pool.fee.unwrap_or(quote::V2_FEE),
+    )
+    .map(|quote| Cors(Json(quote)))
+    .map_err(bad_request)
+}
+
+#[get("/chains/<_>/pools/<pool_id>/quote?<amount_in>&<side>")]
+pub(crate) async fn chain_pool_quote(
+    db: ChainDb<'_>,
+    pool_id: Result<Address, String>,
+    amount_in: Option<&str>,
+    side: Option<&str>,
+) -> Result<Cors<Json<Tagged<quote::Quote>>>, status::Custom<Json<String>>> {
+    let chain = db.chain;
+    pool_quote(db, pool_id, amount_in, side)
+        .await
+        .map(|r| tag(chain, r))
+}
+
+// the best path of up to three pools between two tokens, amount in whole units of from
+#[get("/route?<from>&<to>&<amount>")]
+pub(crate) async fn best_route(
+    mut db: ChainDb<'_>,
+    from: Option<&str>,
+    to: Option<&str>,
+    amount: Option<&str>,
+) -> Result<Cors<Json<quote::Route>>, status::Custom<Json<String>>> {
+    let bad_request = |e: String| status::Custom(Status::BadRequest, Json(e));
+    let (from, to) = match (from, to) {
+        (Some(from), Some(to)) => (address(from.parse())?, address(to.parse())?),
+        _ => return Err(bad_request("from and to required".to_owned())),
+    };
+    if from == to {
+        return Err(bad_request("from and to are the same token".to_owned()));
+    }
+    let amount = match amount.map(BigDecimal::from_str) {
+        Some(Ok(amount)) if amount.is_positive() => amount,
+        Some(Ok(_)) => return Err(bad_request("amount must be positive".to_owned())),
+        Some(Err(e)) => return Err(bad_request(format!("bad amount: {}", e))),
+        None => return Err(bad_request("amount required".to_owned())),
+    };
+    quote::check_amount(&amount).map_err(bad_request)?;
+    quote::best_route(&mut db, &from, &to, &amount)
+        .await
+        .map(|route| Cors(Json(route)))
+        .map_err(|e| match e {
+            quote::RouteError::Input(e) => bad_request(e),
+            quote::RouteError::NotFound(e) => status::Custom(Status::NotFound, Json(e)),
+        })
+}
+
+#[get("/chains/<_>/route?<from>&<to>&<amount>")]
+pub(crate) async fn chain_best_route(
+    db: ChainDb<'_>,
+    from: Option<&str>,
+    to: Option<&str>,
+    amount: Option<&str>,
+) -> Result<Cors<Json<Tagged<quote::Route>>>, status::Custom<Json<String>>> {
+    let chain = db.chain;
+    best_route(db, from, to, amount)
+        .await
+        .map(|r| tag(chain, r))
+}
+
+// new swaps as server-sent events, resuming after Last-Event-ID
+#[get("/pools/<pool_id>/stream")]
+pub(crate) async fn pool_stream(
+    chain: &Chain,
+    pool_id: Result<Address, String>,
+    last_event_id: LastEventId,
+    mut shutdown: Shutdown,
+) -> Result<EventStream![Event + '_], status::Custom<Json<String>>> {
+    let pool_id = address(pool_id)?;
+    let not_found = |e: String| status::Custom(Status::NotFound, Json(e));
+    let unavailable = |e: String| status::Custom(Status::ServiceUnavailable, Json(e));
+    let db = chain.pool();
+    let mut conn = db.acquire().await.map_err(|e| unavailable(e.to_string()))?;
+    let pool = pool::find_by_address(&mut conn, &pool_id)
+        .await
+        .ok_or_else(|| not_found(format!("pool not found {}", pool_id)))?;
+    let (coin0, coin1) = match (
+        coin::find_by_address(&mut conn, &pool.token0).await,
+        coin::find_by_address(&mut conn, &pool.token1).await,
+    ) {
+        (Some(coin0), Some(coin1)) => (coin0, coin1),
+        _ => return Err(not_found(format!("coins not found for pool {}", pool_id))),
+    };
+    let mut cursor = match last_event_id.0 {
+        Some(cursor) => cursor,
+        None => match swap::find_last_id(&mut conn, &pool.contract_address).await {
+            Some(id) => stream::Cursor { id },
+            None => return Err(unavailable("swaps unavailable".to_owned())),
+        },
+    };
+    drop(conn);
+    let poll = Duration::from_secs(chain.config.block_time_secs.max(1));
+    Ok(EventStream! {
+        let mut interval = rocket::tokio::time::interval(poll);
+        loop {
+            select! {
+                _ = interval.tick() => (),
+                _ = &mut shutdown => break,
+            };
+            let mut conn = match db.acquire().await {
+                Ok(conn) => conn,
+                Err(_e) => continue,
+            };
+            let swaps = swap::find_by_pool_after(
+                &mut conn,
+                &pool.contract_address,
+                cursor.id,
+                stream::BATCH,
+            )
+            .await;
+            for swap in swaps {
+                cursor = stream::Cursor::from(&swap);
+                let swap_event = SwapEvent::new(chain.config.chain_id, &coin0, &coin1, swap);
+                yield Event::json(&swap_event).event("swap").id(cursor.event_id());
+            }
+        }
+    }
+    .heartbeat(Duration::from_secs(stream::HEARTBEAT_SECS)))
+}
+
+#[get("/chains/<_>/pools/<pool_id>/stream")]
+pub(crate) async fn chain_pool_stream(
+    chain: &Chain,
+    pool_id: Result<Address, String>,
+    last_event_id: LastEventId,
+    shutdown: Shutdown,
+) -> Result<EventStream![Event + '_], status::Custom<Json<String>>> {
+    pool_stream(chain, pool_id, last_event_id, shutdown).await
+}
+
+/*
+    pool:<address>  swaps since subscribing
+    top:<window>    top pools diff over the window, as in /pools/top?since=
+    block           each newly indexed block
+*/
+#[get("/ws")]
+pub(crate) fn subscribe<'r>(
+    chain: &'r Chain,
+    app_config: &State<AppConfig>,
+    socket: rocket_ws::WebSocket,
+    mut shutdown: Shutdown,
+) -> rocket_ws::Channel<'r> {
+    let mut subscriptions = Subscriptions::new(app_config.ws.max_subscriptions);
+    let poll = Duration::from_secs(chain.config.block_time_secs.max(1));
+    socket.channel(move |mut stream| {
+        Box::pin(async move {
+            let mut interval = rocket::tokio::time::interval(poll);
+            let mut last_block: Option<u32> = None;
+            loop {
+                let replies = select! {
+                    message = stream.next() => match message {
+                        Some(Ok(rocket_ws::Message::Text(text))) => vec![subscriptions.handle(&text)],
+                        Some(Ok(rocket_ws::Message::Close(_))) | None => break,
+                        Some(Ok(_)) => continue,
+                        Some(Err(e)) => return Err(e),
+                    },
+                    _ = interval.tick() => {
+                        let mut conn = match chain.pool().acquire().await {
+                            Ok(conn) => conn,
+                            Err(_e) => continue,
+                        };
+                        let latest = match block::find_latest(&mut conn).await {
+                            Some(latest) => latest,
+                            None => continue,
+                        };
+                        let number: u32 = (&latest.number).into();
+                        if last_block == Some(number) {
+                            continue;
+                        }
+                        last_block = Some(number);
+                        subscriptions.updates(&mut conn, chain, &latest).await
+                    },
+                    _ = &mut shutdown => break,
+                };
+                for reply in replies {
+                    if let Ok(text) = json::to_string(&reply) {
+                        stream.send(rocket_ws::Message::Text(text)).await?;
+                    }
+                }
+            }
+            Ok(())
+        })
+    })
+}
+
+#[get("/chains/<_>/ws")]
+pub(crate) fn chain_subscribe<'r>(
+    chain: &'r Chain,
+    app_config: &State<AppConfig>,
+    socket: rocket_ws::WebSocket,
+    shutdown: Shutdown,
+) -> rocket_ws::Channel<'r> {
+    subscribe(chain, app_config, socket, shutdown)
+}
+
+#[get("/admin/reorg")]
+pub(crate) async fn admin_reorg(
+    _admin: Admin,
+    mut db: ChainDb<'_>,
+) -> Result<Cors<Json<reorg::Reorg>>, status::Custom<Json<String>>> {
+    match reorg::find_last(&mut db).await {
+        Some(reorg) => Ok(Cors(Json(reorg))),
+        None => Err(status::Custom(
+            Status::NotFound,
+            Json("no reorg recorded".to_owned()),
+        )),
+    }
+}
+
+#[get("/chains/<_>/admin/reorg")]
+pub(crate) async fn chain_admin_reorg(
+    admin: Admin,
+    db: ChainDb<'_>,
+) -> Result<Cors<Json<Tagged<reorg::Reorg>>>, status::Custom<Json<String>>> {
+    let chain = db.chain;
+    admin_reorg(admin, db).await.map(|r| tag(chain, r))
+}
+
+#[get("/me/alerts")]
+pub(crate) async fn alerts(
+    account: Account,
+    mut db: Connection<sql::AuthDb>,
+) -> Cors<Json<Vec<alert::Alert>>> {
+    Cors(Json(alert::find_by_account(&mut db, &account.id).await))
+}
+
+#[post("/me/alerts", data = "<new_alert>")]
+pub(crate) async fn alerts_create(
+    account: Account,
+    mut db: Connection<sql::AuthDb>,
+    new_alert: Json<alert::NewAlert>,
+) -> Result<Cors<Json<alert::Alert>>, status::Custom<Json<String>>> {
+    let alert = alert::Alert::from_new(&account.id, new_alert.into_inner())
+        .map_err(|e| status::Custom(Status::BadRequest, Json(e)))?;
+    if pool::find_by_address(&mut db, &alert.pool_contract_address)
+        .await
+        .is_none()
+    {
+        return Err(status::Custom(
+            Status::NotFound,
+            Json(format!("pool not found {}", alert.pool_contract_address)),
+        ));
+    }
+    alert::insert(&mut db, &alert)
+        .await
+        .map_err(|e| status::Custom(Status::InternalServerError, Json(e)))?;
+    Ok(Cors(Json(alert)))
+}
+
+#[put("/me/alerts/<id>", data = "<new_alert>")]
+pub(crate) async fn alerts_update(
+    account: Account,
+    mut db: Connection<sql::AuthDb>,
+    id: &str,
+    new_alert: Json<alert::NewAlert>,
+) -> Result<Cors<Json<alert::Alert>>, status::Custom<Json<String>>> {
+    if alert::find_by_id(&mut db, &account.id, id).await.is_none() {
+        return Err(status::Custom(
+            Status::NotFound,
+            Json(format!("alert not found {}", id)),
+        ));
+    }
+    let mut alert = alert::Alert::from_new(&account.id, new_alert.into_inner())
+        .map_err(|e| status::Custom(Status::BadRequest, Json(e)))?;
+    if pool::find_by_address(&mut db, &alert.pool_contract_address)
+        .await
+        .is_none()
+    {
+        return Err(status::Custom(
+            Status::NotFound,
+            Json(format!("pool not found {}", alert.pool_contract_address)),
+        ));
+    }
+    alert.id = id.to_owned();
+    alert::update(&mut db, &alert)
+        .await
+        .map_err(|e| status::Custom(Status::InternalServerError, Json(e)))?;
+    Ok(Cors(Json(alert)))
+}
+
+#[delete("/me/alerts/<id>")]
+pub(crate) async fn alerts_delete(
+    account: Account,
+    mut db: Connection<sql::AuthDb>,
+    id: &str,
+) -> Cors<Status> {
+    Cors(match alert::delete(&mut db, &account.id, id).await {
+        true => Status::NoContent,
+        false => Status::NotFound,
+    })
+}
+
+#[get("/me/watchlist?<since>")]
+pub(crate) async fn watchlist_pools(
+    chains: &State<Chains>,
+    account: Account,
+    mut db: Connection<sql::AuthDb>,
+    since: Option<&str>,
+) -> Result<Cors<Json<Vec<pool::Pool>>>, status::Custom<Json<String>>> {
+    // watchlists live in auth_db, next to the default chain's data
+    let chain = chains.default_chain();
+    let (start_block, stop_block) = window(&mut db, since).await?;
+    let pool_contract_addresses = watchlist::find_by_account(&mut db, &account.id).await;
+    Ok(Cors(Json(
+        sql::pools_with_sums(
+            &mut db,
+            &pool_contract_addresses,
+            &start_block,
+            &stop_block,
+            &chain.config,
+            &chain.cash,
+        )
+        .await,
+    )))
+}
+
+#[post("/me/watchlist/<pool_id>")]
+pub(crate) async fn watchlist_add(
+    account: Account,
+    mut db: Connection<sql::AuthDb>,
+    pool_id: Result<Address, String>,
+) -> Cors<status::Custom<Json<String>>> {
+    let pool_id = match address(pool_id) {
+        Ok(pool_id) => pool_id,
+        Err(e) => return Cors(e),
+    };
+    let pool = match pool::find_by_address(&mut db, &pool_id).await {
+        Some(pool) => pool,
+        None => {
+            return Cors(status::Custom(
+                Status::NotFound,
+                Json(format!("pool not found {}", pool_id)),
+            ))
+        }
+    };
+    Cors(
+        match watchlist::insert(&mut db, &account.id, &pool.contract_address).await {
+            Ok(_) => status::Custom(Status::Ok, Json(pool.contract_address)),
+            Err(e) => status::Custom(Status::InternalServerError, Json(e)),
+        },
+    )
+}
+
+#[delete("/me/watchlist/<pool_id>")]
+pub(crate) async fn watchlist_delete(
+    account: Account,
+    mut db: Connection<sql::AuthDb>,
+    pool_id: Result<Address, String>,
+) -> Cors<Status> {
+    let pool_id = match pool_id {
+        Ok(pool_id) => pool_id.to_db(),
+        Err(_e) => return Cors(Status::BadRequest),
+    };
+    Cors(
+        match watchlist::delete(&mut db, &account.id, &pool_id).await {
+            true => Status::NoContent,
+            false => Status::NotFound,
+        },
+    )
+}
+
+#[post("/me/digest")]
+pub(crate) async fn digest_subscribe(
+    account: Account,
+    mut db: Connection<sql::AuthDb>,
+) -> Cors<status::Custom<Json<String>>> {
+    Cors(match sql::subscribe_digest(&mut db, &account.id).await {
+        Ok(_) => status::Custom(Status::Ok, Json(account.email)),
+        Err(e) => status::Custom(Status::InternalServerError, Json(e)),
+    })
+}
+
+#[delete("/me/digest")]
+pub(crate) async fn digest_unsubscribe(
+    account: Account,
+    mut db: Connection<sql::AuthDb>,
+) -> Cors<Status> {
+    sql::unsubscribe_digest(&mut db, &account.id).await;
+    Cors(Status::NoContent)
+}
+
+// the link in each digest email, signed instead of needing a login
+#[get("/digest/unsubscribe/<account_id>/<signature>")]
+pub(crate) async fn digest_unsubscribe_link(
+    app_config: &State<AppConfig>,
+    mut db: Connection<sql::AuthDb>,
+    account_id: &str,
+    signature: &str,
+) -> Cors<status::Custom<Json<String>>> {
+    if !digest::verify(&app_config.digest.secret, account_id, signature) {
+        return Cors(status::Custom(
+            Status::Forbidden,
+            Json("bad signature".to_owned()),
+        ));
     }
+    sql::unsubscribe_digest(&mut db, account_id).await;
+    Cors(status::Custom(Status::Ok, Json("unsubscribed".to_owned())))
 }
 
 #[get("/auth/<token>")]
@@ -110,4 +973,77 @@ mod test {
         assert_eq!(response.status(), Status::new(401));
         assert_eq!(response.into_string().unwrap(), "\"bad token\"");
     }
+
+    // launching finalizes the router, which fails on colliding routes
+    #[test]
+    fn launches() {
+        let client = Client::tracked(rocket()).expect("valid rocket instance");
+        let response = client.get("/chains/ethereum/pools/top").dispatch();
+        assert_ne!(response.status(), Status::BadRequest);
+        assert_ne!(response.status(), Status::NotFound);
+    }
+
+    #[test]
+    fn unknown_chain() {
+        let client = Client::tracked(rocket()).expect("valid rocket instance");
+        let response = client.get("/chains/not-a-chain").dispatch();
+        assert_eq!(response.status(), Status::NotFound);
+        let response = client.get("/chains/not-a-chain/pools/top").dispatch();
+        assert_eq!(response.status(), Status::NotFound);
+        let response = client.get("/chains/ethereum").dispatch();
+        assert_eq!(response.status(), Status::Ok);
+    }
+
+    #[test]
+    fn bad_quote() {
+        let client = Client::tracked(rocket()).expect("valid rocket instance");
+        let pool = "b4e16d0168e52d35cacd2c6185b44281ec28c9dc";
+        let response = client
+            .get(format!("/pools/{}/quote?amount_in=1&side=2", pool))
+            .dispatch();
+        assert_eq!(response.status(), Status::BadRequest);
+        assert_eq!(response.into_string().unwrap(), "\"side must be 0 or 1\"");
+        let response = client
+            .get(format!("/pools/{}/quote?side=0", pool))
+            .dispatch();
+        assert_eq!(response.status(), Status::BadRequest);
+        let response = client
+            .get(format!(
+                "/pools/{}/quote?amount_in=1e-999999999&side=0",
+                pool
+            ))
+            .dispatch();
+        assert_eq!(response.status(), Status::BadRequest);
+    }
+
+    #[test]
+    fn bad_route() {
+        let client = Client::tracked(rocket()).expect("valid rocket instance");
+        let weth = "c02aaa39b223fe8d0a0e5c4f27ead9083c756cc2";
+        let response = client
+            .get(format!("/route?from={}&to=0x12&amount=1", weth))
+            .dispatch();
+        assert_eq!(response.status(), Status::BadRequest);
+        let response = client
+            .get(format!("/route?from={}&to={}&amount=1", weth, weth))
+            .dispatch();
+        assert_eq!(response.status(), Status::BadRequest);
+        let usdc = "a0b86991c6218b36c1d19d4a2e9eb0ce3606eb48";
+        let response = client
+            .get(format!(
+                "/route?from={}&to={}&amount=1e999999999",
+                weth, usdc
+            ))
+            .dispatch();
+        assert_eq!(response.status(), Status::BadRequest);
+        let response = client
+            .get(format!("/route?from={}&to={}&amount=0.0000001", usdc, weth))
+            .dispatch();
+        assert_eq!(response.status(), Status::BadRequest);
+        let unknown = "0000000000000000000000000000000000000001";
+        let response = client
+            .get(format!("/route?from={}&to={}&amount=1", weth, unknown))
+            .dispatch();
+        assert_eq!(response.status(), Status::NotFound);
+    }
 }
@@ -1,15 +1,24 @@
 use rocket::fairing::AdHoc;
 use rocket_db_pools::{
-    sqlx::{self, database::HasArguments, query::Query, Postgres, Row},
+    sqlx::{
+        self,
+        database::HasArguments,
+        postgres::PgRow,
+        query::{Query, QueryAs},
+        FromRow, PgConnection, Row,
+    },
     Connection, Database,
 };
 
+use crate::chain::{ChainConfig, Chains};
 use crate::models::{
     account::{self, Account},
     block, coin,
     pool::{self, Pool},
-    reserve,
+    price::Price,
+    reserve, rollup,
 };
+use crate::qury;
 
 #[derive(Database)]
 #[database("auth_db")]
@@ -31,6 +40,7 @@ pub struct AuthDb(sqlx::PgPool);
 //     }
 // }
 
+// auth_db, then every extra chain's database, which carry the same tables
 pub fn migrate() -> AdHoc {
     AdHoc::on_liftoff("SQLx Migrate", |build| {
         Box::pin(async move {
@@ -39,6 +49,19 @@ pub fn migrate() -> AdHoc {
                 Ok(_) => (),
                 Err(e) => error!("migration error: {}", e),
             }
+            let chains = match build.state::<Chains>() {
+                Some(chains) => chains,
+                None => return,
+            };
+            for chain in chains.iter() {
+                if chain.config.database_url.is_none() {
+                    continue;
+                }
+                match sqlx::migrate!("./sql").run(chain.pool()).await {
+                    Ok(_) => (),
+                    Err(e) => error!("migration error, chain {}: {}", chain.config.name, e),
+                }
+            }
         })
     })
 }
@@ -50,15 +73,15 @@ where
     sqlx::query(sql)
 }
 
-impl Account {
-    fn from_row(row: &<Postgres as rocket_db_pools::sqlx::Database>::Row) -> Account {
-        Account {
-            id: row.get::<String, &str>("id"),
-            email: row.get::<String, &str>("email"),
-            token: row.get::<String, &str>("token"),
-        }
-    }
+pub fn query_as<DB, O>(sql: &str) -> QueryAs<'_, DB, O, <DB as HasArguments<'_>>::Arguments>
+where
+    DB: rocket_db_pools::sqlx::Database,
+    O: for<'r> FromRow<'r, DB::Row>,
+{
+    sqlx::query_as(sql)
+}
 
+impl Account {
     pub fn from_email(email: &str) -> Account {
         Account {
             id: account::get_nice_rand_str(),
@@ -69,12 +92,12 @@ impl Account {
 }
 
 pub async fn find_or_create_by_email(mut db: Connection<AuthDb>, email: &str) -> Account {
-    match query("SELECT * FROM auth WHERE email = $1")
+    match query_as("SELECT * FROM auth WHERE email = $1")
         .bind(email)
         .fetch_one(&mut **db)
         .await
     {
-        Ok(row) => Account::from_row(&row),
+        Ok(account) => account,
         Err(_e) => {
             let account = Account::from_email(email);
             insert(db, &account).await;
@@ -84,12 +107,12 @@ pub async fn find_or_create_by_email(mut db: Connection<AuthDb>, email: &str) ->
 }
 
 pub async fn find_by_token(mut db: Connection<AuthDb>, token: &str) -> Option<Account> {
-    match query("SELECT * FROM auth WHERE token = $1")
+    match query_as("SELECT * FROM auth WHERE token = $1")
         .bind(token)
         .fetch_one(&mut **db)
         .await
     {
-        Ok(row) => Some(Account::from_row(&row)),
+        Ok(account) => Some(account),
         Err(_e) => None,
     }
 }
@@ -104,57 +127,105 @@ pub async fn insert(mut db: Connection<AuthDb>, account: &Account) {
         .unwrap();
 }
 
+pub async fn subscribe_digest(db: &mut PgConnection, account_id: &str) -> Result<(), String> {
+    query("INSERT INTO digest_subscriptions (account_id) values ($1) ON CONFLICT DO NOTHING")
+        .bind(account_id)
+        .execute(db)
+        .await
+        .map(|_| ())
+        .map_err(|e| e.to_string())
+}
+
+pub async fn unsubscribe_digest(db: &mut PgConnection, account_id: &str) -> bool {
+    match query("DELETE FROM digest_subscriptions WHERE account_id = $1")
+        .bind(account_id)
+        .execute(db)
+        .await
+    {
+        Ok(result) => result.rows_affected() > 0,
+        Err(_e) => false,
+    }
+}
+
+pub async fn find_digest_subscribers(db: &mut PgConnection) -> Vec<Account> {
+    match query_as("SELECT auth.* FROM auth JOIN digest_subscriptions ON digest_subscriptions.account_id = auth.id")
+        .fetch_all(db)
+        .await
+    {
+        Ok(accounts) => accounts,
+        Err(_e) => vec![],
+    }
+}
+
+const POOL_SUMS_SQL: &str = "select pool_contract_address, sum(in0) as sum_in0, sum(in0_eth) as sum_in0_eth, sum(in1) as sum_in1, sum(in1_eth) as sum_in1_eth, sum(in0_eth + in1_eth) as sum_eth, count(NULLIF(in0,0)) as count0, count(NULLIF(in1,0)) as count1 from swaps where block_number > $1 and block_number <= $2";
+
+// whole hours read from swap_rollups_hourly, the window edges from swaps
+const ROLLUP_SUMS_SQL: &str = "select pool_contract_address, sum(sum_in0) as sum_in0, sum(sum_in0_eth) as sum_in0_eth, sum(sum_in1) as sum_in1, sum(sum_in1_eth) as sum_in1_eth, sum(sum_eth) as sum_eth, sum(count0)::bigint as count0, sum(count1)::bigint as count1 from (select pool_contract_address, sum_in0, sum_in0_eth, sum_in1, sum_in1_eth, sum_eth, count0, count1 from swap_rollups_hourly where hour >= $3 and hour < $4 union all select swaps.pool_contract_address, in0, in0_eth, in1, in1_eth, in0_eth + in1_eth, case when in0 <> 0 then 1 else 0 end, case when in1 <> 0 then 1 else 0 end from swaps join blocks on blocks.number = swaps.block_number where block_number > $1 and block_number <= $2 and (blocks.timestamp < $3 or blocks.timestamp >= $4)) sums";
+
+// sums for one pool or the top ten by eth volume, the given whole hours from the rollups
+pub async fn pool_sums(
+    db: &mut PgConnection,
+    start_block: &block::Number,
+    stop_block: &block::Number,
+    hours: Option<(i32, i32)>,
+    pool_contract_address: Option<&str>,
+) -> Result<Vec<PgRow>, sqlx::Error> {
+    let sql = match (hours, pool_contract_address) {
+        (None, None) => POOL_SUMS_SQL.to_owned(),
+        (None, Some(_)) => format!("{} and pool_contract_address = $3", POOL_SUMS_SQL),
+        (Some(_), None) => ROLLUP_SUMS_SQL.to_owned(),
+        (Some(_), Some(_)) => format!("{} where pool_contract_address = $5", ROLLUP_SUMS_SQL),
+    };
+    let sql = match pool_contract_address {
+        Some(_) => format!("{} group by pool_contract_address", sql),
+        None => format!(
+            "{} group by pool_contract_address order by sum_eth desc limit 10",
+            sql
+        ),
+    };
+    let mut q = query(&sql)
+        .bind::<i32>(start_block.into())
+        .bind::<i32>(stop_block.into());
+    if let Some((from, to)) = hours {
+        q = q.bind(from).bind(to);
+    }
+    if let Some(pool_contract_address) = pool_contract_address {
+        q = q.bind(pool_contract_address);
+    }
+    q.fetch_all(db).await
+}
+
 pub async fn top_pools(
-    mut db: Connection<AuthDb>,
+    db: &mut PgConnection,
     start_block: &block::Number,
     stop_block: &block::Number,
+    chain: &ChainConfig,
+    cash: &coin::CashConfig,
 ) -> Vec<Pool> {
-    let sql = "select pool_contract_address, sum(in0) as sum_in0, sum(in0_eth) as sum_in0_eth, sum(in1) as sum_in1, sum(in1_eth) as sum_in1_eth, sum(in0_eth + in1_eth) as sum_eth, count(NULLIF(in0,0)) as count0, count(NULLIF(in1,0)) as count1 from swaps where block_number > $1 and block_number <= $2 group by pool_contract_address order by sum_eth desc limit 10";
-    match query(sql)
-        .bind::<i32>(start_block.into())
-        .bind::<i32>(stop_block.into())
-        .fetch_all(&mut **db)
-        .await
-    {
+    let hours = rollup::whole_hours(db, start_block, stop_block).await;
+    match pool_sums(db, start_block, stop_block, hours, None).await {
         Ok(rows) => {
+            let eth_usd = qury::native_usd_at(db, chain, cash, stop_block.into())
+                .await
+                .ok();
             let mut r = vec![];
             for row in rows {
                 let pool_contract_address = row.get("pool_contract_address");
-                let mut pool = pool::find_by_address(&mut **db, pool_contract_address)
-                    .await
-                    .unwrap();
-                let reserve = reserve::find_by_address(&mut **db, pool_contract_address)
-                    .await
-                    .unwrap();
-                pool.reserve = Some(reserve);
-                pool.reserve_summary = match pool.has_cash_token() {
-                    true => Some(
-                        reserve::summarize(
-                            &mut **db,
-                            pool_contract_address,
-                            start_block,
-                            stop_block,
-                            pool.cash_token_is_1(),
-                        )
-                        .await,
-                    ),
-                    false => None,
+                // a pool that can no longer be read is left out rather than failing the list
+                let mut pool = match pool::find_by_address(db, pool_contract_address).await {
+                    Some(pool) => pool,
+                    None => continue,
                 };
-                pool.sum0 = Some(row.get::<sqlx::types::BigDecimal, &str>("sum_in0"));
-                pool.sum0_eth = Some(row.get::<sqlx::types::BigDecimal, &str>("sum_in0_eth"));
-                pool.sum1 = Some(row.get::<sqlx::types::BigDecimal, &str>("sum_in1"));
-                pool.sum1_eth = Some(row.get::<sqlx::types::BigDecimal, &str>("sum_in1_eth"));
-                pool.sum_eth = Some(row.get::<sqlx::types::BigDecimal, &str>("sum_eth"));
-                pool.count0 = Some(row.get::<i64, &str>("count0"));
-                pool.count1 = Some(row.get::<i64, &str>("count1"));
-                let coin0 = coin::find_by_address(&mut **db, &pool.token0)
-                    .await
-                    .unwrap();
-                pool.coin0 = Some(coin0);
-                let coin1 = coin::find_by_address(&mut **db, &pool.token1)
-                    .await
-                    .unwrap();
-                pool.coin1 = Some(coin1);
+                set_sums(&mut pool, &row);
+                enrich_pool(
+                    db,
+                    &mut pool,
+                    start_block,
+                    stop_block,
+                    cash,
+                    eth_usd.as_ref(),
+                )
+                .await;
                 r.push(pool)
             }
             r
@@ -162,3 +233,95 @@ pub async fn top_pools(
         Err(_e) => vec![],
     }
 }
+
+// the given pools enriched the same way as top_pools, in the given order
+pub async fn pools_with_sums(
+    db: &mut PgConnection,
+    pool_contract_addresses: &[String],
+    start_block: &block::Number,
+    stop_block: &block::Number,
+    chain: &ChainConfig,
+    cash: &coin::CashConfig,
+) -> Vec<Pool> {
+    let hours = rollup::whole_hours(db, start_block, stop_block).await;
+    let eth_usd = qury::native_usd_at(db, chain, cash, stop_block.into())
+        .await
+        .ok();
+    let mut r = vec![];
+    for pool_contract_address in pool_contract_addresses {
+        let mut pool = match pool::find_by_address(db, pool_contract_address).await {
+            Some(pool) => pool,
+            None => continue,
+        };
+        // no swaps in the window leaves the sums empty
+        if let Ok(Some(row)) = pool_sums(
+            db,
+            start_block,
+            stop_block,
+            hours,
+            Some(pool_contract_address),
+        )
+        .await
+        .map(|mut rows| rows.pop())
+        {
+            set_sums(&mut pool, &row);
+        }
+        enrich_pool(
+            db,
+            &mut pool,
+            start_block,
+            stop_block,
+            cash,
+            eth_usd.as_ref(),
+        )
+        .await;
+        r.push(pool)
+    }
+    r
+}
+
+fn set_sums(pool: &mut Pool, row: &PgRow) {
+    pool.sum0 = Some(row.get::<sqlx::types::BigDecimal, &str>("sum_in0"));
+    pool.sum0_eth = Some(row.get::<sqlx::types::BigDecimal, &str>("sum_in0_eth"));
+    pool.sum1 = Some(row.get::<sqlx::types::BigDecimal, &str>("sum_in1"));
+    pool.sum1_eth = Some(row.get::<sqlx::types::BigDecimal, &str>("sum_in1_eth"));
+    pool.sum_eth = Some(row.get::<sqlx::types::BigDecimal, &str>("sum_eth"));
+    pool.count0 = Some(row.get::<i64, &str>("count0"));
+    pool.count1 = Some(row.get::<i64, &str>("count1"));
+}
+
+// coins, latest reserve, reserve summary and usd values for one pool
+async fn enrich_pool(
+    db: &mut PgConnection,
+    pool: &mut Pool,
+    start_block: &block::Number,
+    stop_block: &block::Number,
+    cash: &coin::CashConfig,
+    eth_usd: Option<&Price>,
+) {
+    let coin_cash_order = match pool.has_cash_token(cash) {
+        true => Some(pool.cash_token_is_1(cash)),
+        false => None,
+    };
+    pool.reserve_summary = reserve::summarize(
+        db,
+        &pool.contract_address,
+        start_block,
+        stop_block,
+        coin_cash_order,
+    )
+    .await;
+    pool.volume_usd = pool
+        .sum_eth
+        .as_ref()
+        .and_then(|sum_eth| qury::eth_to_usd(sum_eth, eth_usd));
+    let reserve = reserve::find_by_address(db, &pool.contract_address).await;
+    let coin0 = coin::find_by_address(db, &pool.token0).await;
+    let coin1 = coin::find_by_address(db, &pool.token1).await;
+    if let (Some(reserve), Some(coin0), Some(coin1)) = (&reserve, &coin0, &coin1) {
+        pool.reserve_usd = qury::reserve_usd(db, cash, coin0, coin1, reserve).await;
+    }
+    pool.reserve = reserve;
+    pool.coin0 = coin0;
+    pool.coin1 = coin1;
+}
@@ -0,0 +1,93 @@
+use rocket::http::Status;
+use rocket::outcome::Outcome;
+use rocket::request::{self, FromRequest};
+use rocket::serde::Serialize;
+use rocket::Request;
+
+use crate::models::{coin::Coin, price::Price, swap::Swap};
+
+// how many swaps one poll pushes at most
+pub const BATCH: i64 = 100;
+
+// seconds between heartbeat comments on an idle stream
+pub const HEARTBEAT_SECS: u64 = 15;
+
+// stream position, the id of the last sent swap
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Cursor {
+    pub id: i64,
+}
+
+impl Cursor {
+    pub fn parse(id: &str) -> Option<Cursor> {
+        match id.parse() {
+            Ok(id) if id >= 0 => Some(Cursor { id }),
+            _ => None,
+        }
+    }
+
+    pub fn event_id(&self) -> String {
+        self.id.to_string()
+    }
+}
+
+impl From<&Swap> for Cursor {
+    fn from(swap: &Swap) -> Self {
+        Cursor { id: swap.id }
+    }
+}
+
+// the Last-Event-ID an EventSource sends when it reconnects
+pub struct LastEventId(pub Option<Cursor>);
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for LastEventId {
+    type Error = &'static str;
+
+    async fn from_request(req: &'r Request<'_>) -> request::Outcome<Self, Self::Error> {
+        match req.headers().get_one("Last-Event-ID") {
+            // block-transaction ids from before swap ids restart at the newest swap
+            Some(id) if id.contains('-') => Outcome::Success(LastEventId(None)),
+            Some(id) => match Cursor::parse(id) {
+                Some(cursor) => Outcome::Success(LastEventId(Some(cursor))),
+                None => Outcome::Error((Status::BadRequest, "bad Last-Event-ID")),
+            },
+            None => Outcome::Success(LastEventId(None)),
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub struct SwapEvent {
+    pub chain_id: u64,
+    // token0 in units of token1
+    pub price0: Option<Price>,
+    // token1 in units of token0
+    pub price1: Option<Price>,
+    pub swap: Swap,
+}
+
+impl SwapEvent {
+    pub fn new(chain_id: u64, coin0: &Coin, coin1: &Coin, swap: Swap) -> SwapEvent {
+        SwapEvent {
+            chain_id,
+            price0: swap.price(false, coin0.decimals, coin1.decimals).ok(),
+            price1: swap.price(true, coin0.decimals, coin1.decimals).ok(),
+            swap,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Cursor;
+
+    #[test]
+    fn cursor_roundtrip() {
+        let cursor = Cursor { id: 4_210_377 };
+        assert_eq!(cursor.event_id(), "4210377");
+        assert_eq!(Cursor::parse("4210377"), Some(cursor));
+        assert_eq!(Cursor::parse("a"), None);
+        assert_eq!(Cursor::parse(""), None);
+    }
+}
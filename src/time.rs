@@ -0,0 +1,100 @@
+use time::format_description::well_known::Rfc3339;
+use time::macros::format_description;
+use time::{Date, Duration, OffsetDateTime};
+
+/// Start of a query window, relative to the newest block or at a fixed time.
+#[derive(Debug, PartialEq)]
+pub enum Since {
+    Ago(Duration),
+    At(OffsetDateTime),
+}
+
+impl Default for Since {
+    fn default() -> Self {
+        Since::Ago(Duration::hours(24))
+    }
+}
+
+impl Since {
+    /*
+        30m, 6h, 7d       duration before the newest block
+        24                bare number is hours
+        2024-09-15        midnight UTC
+        2024-09-15T12:00:00Z
+    */
+    pub fn parse(since: &str) -> Result<Since, String> {
+        let since = since.trim();
+        if let Ok(at) = OffsetDateTime::parse(since, &Rfc3339) {
+            return Ok(Since::At(at));
+        }
+        if let Ok(date) = Date::parse(since, format_description!("[year]-[month]-[day]")) {
+            return Ok(Since::At(date.midnight().assume_utc()));
+        }
+        let (count, unit_secs) = match since.char_indices().last() {
+            Some((idx, 's')) => (&since[..idx], 1),
+            Some((idx, 'm')) => (&since[..idx], 60),
+            Some((idx, 'h')) => (&since[..idx], 60 * 60),
+            Some((idx, 'd')) => (&since[..idx], 24 * 60 * 60),
+            Some((idx, 'w')) => (&since[..idx], 7 * 24 * 60 * 60),
+            Some(_) => (since, 60 * 60),
+            None => return Err("since is empty".to_owned()),
+        };
+        match count.parse::<u32>() {
+            Ok(count) => Ok(Since::Ago(Duration::seconds(count as i64 * unit_secs))),
+            Err(_) => Err(format!("bad since {}", since)),
+        }
+    }
+
+    pub fn timestamp(&self, now: OffsetDateTime) -> OffsetDateTime {
+        match self {
+            Since::Ago(duration) => match now.checked_sub(*duration) {
+                Some(start) if start > OffsetDateTime::UNIX_EPOCH => start,
+                _ => OffsetDateTime::UNIX_EPOCH,
+            },
+            Since::At(at) => *at,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Since;
+    use time::macros::datetime;
+    use time::Duration;
+
+    #[test]
+    fn parse_durations() {
+        assert_eq!(Since::parse("30m"), Ok(Since::Ago(Duration::minutes(30))));
+        assert_eq!(Since::parse("6h"), Ok(Since::Ago(Duration::hours(6))));
+        assert_eq!(Since::parse("7d"), Ok(Since::Ago(Duration::days(7))));
+        assert_eq!(Since::parse("24"), Ok(Since::Ago(Duration::hours(24))));
+    }
+
+    #[test]
+    fn parse_timestamps() {
+        assert_eq!(
+            Since::parse("2024-09-15T12:30:00Z"),
+            Ok(Since::At(datetime!(2024-09-15 12:30:00 UTC)))
+        );
+        assert_eq!(
+            Since::parse("2024-09-15"),
+            Ok(Since::At(datetime!(2024-09-15 00:00:00 UTC)))
+        );
+    }
+
+    #[test]
+    fn parse_rejects_garbage() {
+        assert!(Since::parse("").is_err());
+        assert!(Since::parse("-6h").is_err());
+        assert!(Since::parse("six hours").is_err());
+    }
+
+    #[test]
+    fn timestamp_saturates() {
+        let now = datetime!(1970-01-02 00:00:00 UTC);
+        assert_eq!(
+            Since::Ago(Duration::days(7)).timestamp(now),
+            time::OffsetDateTime::UNIX_EPOCH
+        );
+    }
+}
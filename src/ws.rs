@@ -0,0 +1,353 @@
+use rocket::serde::json::{self, Value};
+use rocket::serde::{Deserialize, Serialize};
+use rocket_db_pools::sqlx::PgConnection;
+use std::collections::HashMap;
+
+use crate::address::Address;
+use crate::chain::Chain;
+use crate::models::{block, coin, pool, swap};
+use crate::sql;
+use crate::stream::{self, Cursor, SwapEvent};
+use crate::time::Since;
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(crate = "rocket::serde", default)]
+pub struct WsConfig {
+    // topics one connection may hold at once
+    pub max_subscriptions: usize,
+}
+
+impl Default for WsConfig {
+    fn default() -> Self {
+        WsConfig {
+            max_subscriptions: 10,
+        }
+    }
+}
+
+/*
+    {"op": "subscribe", "topic": "pool:0xB4e16d0168e52d35CaCD2c6185b44281Ec28C9Dc"}
+    {"op": "subscribe", "topic": "top:24h"}
+    {"op": "unsubscribe", "topic": "block"}
+*/
+#[derive(Deserialize, Debug, PartialEq)]
+#[serde(crate = "rocket::serde", tag = "op", rename_all = "lowercase")]
+pub enum ClientMessage {
+    Subscribe { topic: String },
+    Unsubscribe { topic: String },
+}
+
+#[derive(Serialize, Debug, PartialEq)]
+#[serde(crate = "rocket::serde", tag = "type", rename_all = "lowercase")]
+pub enum ServerMessage {
+    Subscribed {
+        topic: String,
+    },
+    Unsubscribed {
+        topic: String,
+    },
+    Error {
+        message: String,
+    },
+    Update {
+        topic: String,
+        block_number: u32,
+        data: Value,
+    },
+}
+
+pub enum Topic {
+    Block,
+    // new swaps after cursor
+    Pool {
+        address: String,
+        cursor: Option<Cursor>,
+    },
+    // top pools over a window, diffed against the last push
+    Top {
+        since: Since,
+        previous: Vec<(String, Value)>,
+    },
+}
+
+impl Topic {
+    // canonical topic name and its fresh state
+    pub fn parse(topic: &str) -> Result<(String, Topic), String> {
+        match topic.split_once(':') {
+            None if topic == "block" => Ok(("block".to_owned(), Topic::Block)),
+            Some(("pool", address)) => {
+                let address = address.parse::<Address>()?.to_db();
+                Ok((
+                    format!("pool:{}", address),
+                    Topic::Pool {
+                        address,
+                        cursor: None,
+                    },
+                ))
+            }
+            Some(("top", window)) => Ok((
+                format!("top:{}", window),
+                Topic::Top {
+                    since: Since::parse(window)?,
+                    previous: vec![],
+                },
+            )),
+            _ => Err(format!("bad topic {}", topic)),
+        }
+    }
+}
+
+pub struct Subscriptions {
+    limit: usize,
+    topics: Vec<(String, Topic)>,
+}
+
+impl Subscriptions {
+    pub fn new(limit: usize) -> Subscriptions {
+        Subscriptions {
+            limit,
+            topics: vec![],
+        }
+    }
+
+    // applies one client text frame, the reply goes straight back
+    pub fn handle(&mut self, text: &str) -> ServerMessage {
+        let message = match json::from_str::<ClientMessage>(text) {
+            Ok(message) => message,
+            Err(e) => {
+                return ServerMessage::Error {
+                    message: e.to_string(),
+                }
+            }
+        };
+        let (name, topic) = match &message {
+            ClientMessage::Subscribe { topic } | ClientMessage::Unsubscribe { topic } => {
+                match Topic::parse(topic) {
+                    Ok(parsed) => parsed,
+                    Err(message) => return ServerMessage::Error { message },
+                }
+            }
+        };
+        let existing = self.topics.iter().position(|(held, _)| *held == name);
+        match (message, existing) {
+            (ClientMessage::Subscribe { .. }, Some(_)) => ServerMessage::Subscribed { topic: name },
+            (ClientMessage::Subscribe { .. }, None) if self.topics.len() >= self.limit => {
+                ServerMessage::Error {
+                    message: format!("at most {} subscriptions", self.limit),
+                }
+            }
+            (ClientMessage::Subscribe { .. }, None) => {
+                self.topics.push((name.clone(), topic));
+                ServerMessage::Subscribed { topic: name }
+            }
+            (ClientMessage::Unsubscribe { .. }, Some(i)) => {
+                self.topics.remove(i);
+                ServerMessage::Unsubscribed { topic: name }
+            }
+            (ClientMessage::Unsubscribe { .. }, None) => ServerMessage::Error {
+                message: format!("not subscribed to {}", name),
+            },
+        }
+    }
+
+    // one update per topic with something new at this block
+    pub async fn updates(
+        &mut self,
+        db: &mut PgConnection,
+        chain: &Chain,
+        latest: &block::Block,
+    ) -> Vec<ServerMessage> {
+        let block_number: u32 = (&latest.number).into();
+        let mut r = vec![];
+        for (name, topic) in self.topics.iter_mut() {
+            let data = match topic {
+                Topic::Block => json::to_value(latest).ok(),
+                Topic::Pool { address, cursor } => pool_update(db, chain, address, cursor).await,
+                Topic::Top { since, previous } => {
+                    let start_block = block::find_since(db, latest, since).await;
+                    let pools = sql::top_pools(
+                        db,
+                        &start_block,
+                        &latest.number,
+                        &chain.config,
+                        &chain.cash,
+                    )
+                    .await
+                    .iter()
+                    .filter_map(|pool| {
+                        Some((pool.contract_address.clone(), json::to_value(pool).ok()?))
+                    })
+                    .collect();
+                    let diff = top_diff(previous, &pools);
+                    *previous = pools;
+                    diff.and_then(|diff| json::to_value(diff).ok())
+                }
+            };
+            if let Some(data) = data {
+                r.push(ServerMessage::Update {
+                    topic: name.clone(),
+                    block_number,
+                    data,
+                });
+            }
+        }
+        r
+    }
+}
+
+// swaps since the topic's cursor, the first call only marks the starting point
+async fn pool_update(
+    db: &mut PgConnection,
+    chain: &Chain,
+    address: &str,
+    cursor: &mut Option<Cursor>,
+) -> Option<Value> {
+    let after = match cursor {
+        Some(after) => *after,
+        None => {
+            *cursor = Some(Cursor {
+                id: swap::find_last_id(db, address).await?,
+            });
+            return None;
+        }
+    };
+    let pool = pool::find_by_address(db, address).await?;
+    let coin0 = coin::find_by_address(db, &pool.token0).await?;
+    let coin1 = coin::find_by_address(db, &pool.token1).await?;
+    let swaps = swap::find_by_pool_after(db, address, after.id, stream::BATCH).await;
+    let last = swaps.last().map(Cursor::from)?;
+    *cursor = Some(last);
+    let events: Vec<SwapEvent> = swaps
+        .into_iter()
+        .map(|swap| SwapEvent::new(chain.config.chain_id, &coin0, &coin1, swap))
+        .collect();
+    json::to_value(events).ok()
+}
+
+#[derive(Serialize, Debug, PartialEq)]
+#[serde(crate = "rocket::serde")]
+pub struct TopDiff {
+    // pool addresses in rank order
+    pub order: Vec<String>,
+    // pools that are new to the list or whose figures moved
+    pub changed: Vec<Value>,
+    // pools that dropped off the list
+    pub removed: Vec<String>,
+}
+
+// None when nothing moved
+pub fn top_diff(previous: &[(String, Value)], next: &[(String, Value)]) -> Option<TopDiff> {
+    let before: HashMap<&str, &Value> = previous
+        .iter()
+        .map(|(address, pool)| (address.as_str(), pool))
+        .collect();
+    let order: Vec<String> = next.iter().map(|(address, _)| address.clone()).collect();
+    let changed: Vec<Value> = next
+        .iter()
+        .filter(|(address, pool)| before.get(address.as_str()) != Some(&pool))
+        .map(|(_, pool)| pool.clone())
+        .collect();
+    let removed: Vec<String> = previous
+        .iter()
+        .filter(|(address, _)| !order.contains(address))
+        .map(|(address, _)| address.clone())
+        .collect();
+    let reordered = previous.iter().map(|(address, _)| address).ne(order.iter());
+    match changed.is_empty() && removed.is_empty() && !reordered {
+        true => None,
+        false => Some(TopDiff {
+            order,
+            changed,
+            removed,
+        }),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{top_diff, ServerMessage, Subscriptions};
+    use rocket::serde::json::{json, Value};
+
+    #[test]
+    fn subscribe_limit() {
+        let mut subscriptions = Subscriptions::new(2);
+        assert_eq!(
+            subscriptions.handle(r#"{"op": "subscribe", "topic": "block"}"#),
+            ServerMessage::Subscribed {
+                topic: "block".to_owned()
+            }
+        );
+        assert_eq!(
+            subscriptions.handle(
+                r#"{"op": "subscribe", "topic": "pool:0xB4e16d0168e52d35CaCD2c6185b44281Ec28C9Dc"}"#
+            ),
+            ServerMessage::Subscribed {
+                topic: "pool:b4e16d0168e52d35cacd2c6185b44281ec28c9dc".to_owned()
+            }
+        );
+        // already held topics don't count twice
+        assert_eq!(
+            subscriptions.handle(r#"{"op": "subscribe", "topic": "block"}"#),
+            ServerMessage::Subscribed {
+                topic: "block".to_owned()
+            }
+        );
+        assert!(matches!(
+            subscriptions.handle(r#"{"op": "subscribe", "topic": "top:24h"}"#),
+            ServerMessage::Error { .. }
+        ));
+        assert_eq!(
+            subscriptions.handle(r#"{"op": "unsubscribe", "topic": "block"}"#),
+            ServerMessage::Unsubscribed {
+                topic: "block".to_owned()
+            }
+        );
+        assert_eq!(
+            subscriptions.handle(r#"{"op": "subscribe", "topic": "top:24h"}"#),
+            ServerMessage::Subscribed {
+                topic: "top:24h".to_owned()
+            }
+        );
+    }
+
+    #[test]
+    fn bad_messages() {
+        let mut subscriptions = Subscriptions::new(2);
+        for text in [
+            "not json",
+            r#"{"op": "publish", "topic": "block"}"#,
+            r#"{"op": "subscribe", "topic": "pool:0x1234"}"#,
+            r#"{"op": "subscribe", "topic": "top:soon"}"#,
+            r#"{"op": "subscribe", "topic": "blocks"}"#,
+            r#"{"op": "unsubscribe", "topic": "block"}"#,
+        ] {
+            assert!(
+                matches!(subscriptions.handle(text), ServerMessage::Error { .. }),
+                "{}",
+                text
+            );
+        }
+    }
+
+    #[test]
+    fn diff() {
+        let pool = |address: &str, sum: u32| (address.to_owned(), json!({ "sum_eth": sum }));
+        let previous: Vec<(String, Value)> = vec![pool("a", 3), pool("b", 2), pool("c", 1)];
+        assert_eq!(top_diff(&previous, &previous), None);
+
+        let next = vec![pool("b", 4), pool("a", 3), pool("d", 1)];
+        let diff = top_diff(&previous, &next).unwrap();
+        assert_eq!(diff.order, vec!["b", "a", "d"]);
+        assert_eq!(
+            diff.changed,
+            vec![json!({ "sum_eth": 4 }), json!({ "sum_eth": 1 })]
+        );
+        assert_eq!(diff.removed, vec!["c"]);
+
+        // a reorder alone is still news
+        let next = vec![pool("b", 2), pool("a", 3), pool("c", 1)];
+        let diff = top_diff(&previous, &next).unwrap();
+        assert!(diff.changed.is_empty());
+        assert_eq!(diff.order, vec!["b", "a", "c"]);
+    }
+}